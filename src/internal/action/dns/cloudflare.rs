@@ -0,0 +1,142 @@
+use super::DnsProvider;
+
+use async_trait::async_trait;
+use reqwest::Method;
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::net::IpAddr;
+use std::time::Duration;
+
+pub struct CloudflareProvider {
+    base_url: String,
+    token: String,
+    zone_id: String,
+    timeout: Duration,
+}
+
+#[derive(Debug, Deserialize)]
+struct ApiResponse<T> {
+    success: bool,
+    #[serde(default)]
+    errors: Vec<ApiMessage>,
+    result: Option<T>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ApiMessage {
+    code: i64,
+    message: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct DnsRecord {
+    id: String,
+    content: String,
+}
+
+#[derive(Debug, Serialize)]
+struct NewDnsRecord<'a> {
+    #[serde(rename = "type")]
+    kind: &'a str,
+    name: &'a str,
+    content: String,
+    ttl: u32,
+    proxied: bool,
+}
+
+impl CloudflareProvider {
+    pub fn build(base_url: &str, token: &str, zone_id: &str, timeout: Duration) -> Self {
+        Self {
+            base_url: base_url.trim_end_matches('/').to_string(),
+            token: token.to_string(),
+            zone_id: zone_id.to_string(),
+            timeout,
+        }
+    }
+
+    async fn call<T: for<'de> Deserialize<'de>>(
+        &self,
+        client: &reqwest::Client,
+        method: Method,
+        path: &str,
+        query: &[(&str, &str)],
+        body: Option<String>,
+    ) -> Result<Option<T>, Box<dyn Error>> {
+        let mut url = Url::parse(&format!("{}/zones/{}{}", self.base_url, self.zone_id, path))?;
+        // 查询参数需要编码，避免名称中的特殊字符破坏查询串
+        if !query.is_empty() {
+            url.query_pairs_mut().extend_pairs(query);
+        }
+        let mut req = client.request(method, url).bearer_auth(&self.token);
+        if let Some(body) = body {
+            req = req
+                .header(reqwest::header::CONTENT_TYPE, "application/json")
+                .body(body);
+        }
+
+        let text = req.send().await?.text().await?;
+        let resp: ApiResponse<T> = serde_json::from_str(&text)?;
+        if !resp.success {
+            let errors: Vec<String> = resp
+                .errors
+                .iter()
+                .map(|x| format!("{}: {}", x.code, x.message))
+                .collect();
+            return Err(format!("cloudflare api error: {}", errors.join(", ")).into());
+        }
+        Ok(resp.result)
+    }
+}
+
+#[async_trait]
+impl DnsProvider for CloudflareProvider {
+    async fn set_records(
+        &self,
+        name: &str,
+        ips: &[IpAddr],
+        ttl: u32,
+    ) -> Result<(), Box<dyn Error>> {
+        let kind = if ips[0].is_ipv4() { "A" } else { "AAAA" };
+        let client = reqwest::Client::builder().timeout(self.timeout).build()?;
+
+        let query = [("type", kind), ("name", name)];
+        let existing: Vec<DnsRecord> = self
+            .call(&client, Method::GET, "/dns_records", &query, None)
+            .await?
+            .unwrap_or_default();
+
+        // 保留已存在的记录，不在新列表中的记录在新记录创建之后再删除，
+        // 中途失败时域名仍然有可用的记录
+        let mut kept = Vec::new();
+        let mut stale = Vec::new();
+        for record in existing {
+            match record.content.parse::<IpAddr>() {
+                Ok(ip) if ips.contains(&ip) && !kept.contains(&ip) => kept.push(ip),
+                _ => stale.push(record.id),
+            }
+        }
+
+        for ip in ips.iter().filter(|x| !kept.contains(x)) {
+            let body = serde_json::to_string(&NewDnsRecord {
+                kind,
+                name,
+                content: ip.to_string(),
+                ttl,
+                proxied: false,
+            })?;
+            self.call::<serde_json::Value>(&client, Method::POST, "/dns_records", &[], Some(body))
+                .await?;
+        }
+        for id in stale {
+            let path = format!("/dns_records/{id}");
+            self.call::<serde_json::Value>(&client, Method::DELETE, &path, &[], None)
+                .await?;
+        }
+        Ok(())
+    }
+
+    fn get_name(&self) -> &str {
+        "cloudflare"
+    }
+}
@@ -0,0 +1,92 @@
+pub mod cloudflare;
+pub mod rfc2136;
+
+use crate::internal::client::download::DownloadTestResult;
+use crate::internal::config::def::Config;
+
+use async_trait::async_trait;
+use std::error::Error;
+use std::net::IpAddr;
+use std::time::Duration;
+use tokio::runtime::Runtime;
+
+use cloudflare::CloudflareProvider;
+use rfc2136::Rfc2136Provider;
+
+#[async_trait]
+pub trait DnsProvider {
+    // 把 name 的 A 或 AAAA 记录设置为 ips，ips 中的地址类型一致
+    async fn set_records(&self, name: &str, ips: &[IpAddr], ttl: u32)
+        -> Result<(), Box<dyn Error>>;
+
+    fn get_name(&self) -> &str;
+}
+
+pub fn create_provider(conf: &Config) -> Result<Box<dyn DnsProvider>, Box<dyn Error>> {
+    match conf.dns.provider.as_str() {
+        "cloudflare" => {
+            let c = &conf.dns.cloudflare;
+            Ok(Box::new(CloudflareProvider::build(
+                c.base_url.as_str(),
+                c.token.as_str(),
+                c.zone_id.as_str(),
+                Duration::from_secs(c.timeout),
+            )))
+        }
+        "rfc2136" => {
+            let c = &conf.dns.rfc2136;
+            Ok(Box::new(Rfc2136Provider::build(
+                c.server.parse()?,
+                c.zone.as_str(),
+                Duration::from_secs(c.timeout),
+            )))
+        }
+        others => Err(format!("invalid dns provider: {others}").into()),
+    }
+}
+
+// 按地址类型拆分，分别更新 A 和 AAAA 记录
+pub async fn update_records(
+    provider: &dyn DnsProvider,
+    names: &[String],
+    ips: &[IpAddr],
+    ttl: u32,
+) -> Result<(), Box<dyn Error>> {
+    let v4: Vec<IpAddr> = ips.iter().filter(|x| x.is_ipv4()).cloned().collect();
+    let v6: Vec<IpAddr> = ips.iter().filter(|x| x.is_ipv6()).cloned().collect();
+
+    for name in names {
+        for group in [&v4, &v6] {
+            if group.is_empty() {
+                continue;
+            }
+            provider.set_records(name, group, ttl).await?;
+            println!(
+                "已通过 {} 将 {name} 更新为 {:?}",
+                provider.get_name(),
+                group
+            );
+        }
+    }
+    Ok(())
+}
+
+pub fn update(conf: &Config, result: &DownloadTestResult) -> Result<(), Box<dyn Error>> {
+    let ips = result.top_ips(conf.dns.top.max(1));
+    if ips.is_empty() {
+        println!("没有可用的下载测速数据，跳过 DNS 更新");
+        return Ok(());
+    }
+
+    if conf.dns.records.is_empty() {
+        return Err("dns.records 中没有需要更新的记录".into());
+    }
+
+    let provider = create_provider(conf)?;
+    Runtime::new()?.block_on(update_records(
+        provider.as_ref(),
+        &conf.dns.records,
+        &ips,
+        conf.dns.ttl,
+    ))
+}
@@ -0,0 +1,131 @@
+use super::DnsProvider;
+
+use async_trait::async_trait;
+use std::error::Error;
+use std::net::{IpAddr, SocketAddr};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::{net::UdpSocket, time};
+
+const OPCODE_UPDATE: u16 = 5;
+const TYPE_A: u16 = 1;
+const TYPE_SOA: u16 = 6;
+const TYPE_AAAA: u16 = 28;
+const CLASS_IN: u16 = 1;
+const CLASS_ANY: u16 = 255;
+
+// 不带 TSIG 签名的 RFC 2136 动态更新
+pub struct Rfc2136Provider {
+    server: SocketAddr,
+    zone: String,
+    timeout: Duration,
+}
+
+impl Rfc2136Provider {
+    pub fn build(server: SocketAddr, zone: &str, timeout: Duration) -> Self {
+        Self {
+            server,
+            zone: zone.to_string(),
+            timeout,
+        }
+    }
+}
+
+fn push_u16(buf: &mut Vec<u8>, x: u16) {
+    buf.extend_from_slice(&x.to_be_bytes());
+}
+
+fn push_name(buf: &mut Vec<u8>, name: &str) -> Result<(), Box<dyn Error>> {
+    for label in name.trim_end_matches('.').split('.') {
+        if label.is_empty() || label.len() > 63 {
+            return Err(format!("invalid dns name: {name}").into());
+        }
+        buf.push(label.len() as u8);
+        buf.extend_from_slice(label.as_bytes());
+    }
+    buf.push(0);
+    Ok(())
+}
+
+// 生成更新报文：先删除 name 上该类型的全部记录，再逐条添加 ips
+pub fn build_message(
+    id: u16,
+    zone: &str,
+    name: &str,
+    ips: &[IpAddr],
+    ttl: u32,
+) -> Result<Vec<u8>, Box<dyn Error>> {
+    let kind = if ips[0].is_ipv4() { TYPE_A } else { TYPE_AAAA };
+
+    let mut buf = Vec::new();
+    push_u16(&mut buf, id);
+    push_u16(&mut buf, OPCODE_UPDATE << 11);
+    push_u16(&mut buf, 1);
+    push_u16(&mut buf, 0);
+    push_u16(&mut buf, ips.len() as u16 + 1);
+    push_u16(&mut buf, 0);
+
+    // zone
+    push_name(&mut buf, zone)?;
+    push_u16(&mut buf, TYPE_SOA);
+    push_u16(&mut buf, CLASS_IN);
+
+    // 删除 RRset
+    push_name(&mut buf, name)?;
+    push_u16(&mut buf, kind);
+    push_u16(&mut buf, CLASS_ANY);
+    buf.extend_from_slice(&0u32.to_be_bytes());
+    push_u16(&mut buf, 0);
+
+    for ip in ips {
+        let rdata = match ip {
+            IpAddr::V4(ip) => ip.octets().to_vec(),
+            IpAddr::V6(ip) => ip.octets().to_vec(),
+        };
+        push_name(&mut buf, name)?;
+        push_u16(&mut buf, kind);
+        push_u16(&mut buf, CLASS_IN);
+        buf.extend_from_slice(&ttl.to_be_bytes());
+        push_u16(&mut buf, rdata.len() as u16);
+        buf.extend_from_slice(&rdata);
+    }
+    Ok(buf)
+}
+
+#[async_trait]
+impl DnsProvider for Rfc2136Provider {
+    async fn set_records(
+        &self,
+        name: &str,
+        ips: &[IpAddr],
+        ttl: u32,
+    ) -> Result<(), Box<dyn Error>> {
+        let id = SystemTime::now().duration_since(UNIX_EPOCH)?.subsec_nanos() as u16;
+        let message = build_message(id, &self.zone, name, ips, ttl)?;
+
+        let local: SocketAddr = if self.server.is_ipv4() {
+            "0.0.0.0:0".parse()?
+        } else {
+            "[::]:0".parse()?
+        };
+        let socket = UdpSocket::bind(local).await?;
+        socket.send_to(&message, self.server).await?;
+
+        let mut resp = [0u8; 512];
+        loop {
+            let (n, from) = time::timeout(self.timeout, socket.recv_from(&mut resp)).await??;
+            if from != self.server || n < 12 || u16::from_be_bytes([resp[0], resp[1]]) != id {
+                continue;
+            }
+
+            let flags = u16::from_be_bytes([resp[2], resp[3]]);
+            return match flags & 0xf {
+                0 => Ok(()),
+                rcode => Err(format!("dns update refused, rcode {rcode}").into()),
+            };
+        }
+    }
+
+    fn get_name(&self) -> &str {
+        "rfc2136"
+    }
+}
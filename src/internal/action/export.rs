@@ -0,0 +1,252 @@
+use crate::internal::client::download::DownloadTestResult;
+use crate::internal::config::def::Config;
+
+use reqwest::Url;
+use serde_json::{json, Value};
+use std::error::Error;
+use std::fs;
+use std::net::SocketAddr;
+
+// 模板格式
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Format {
+    Clash,
+    SingBox,
+    Xray,
+}
+
+impl Format {
+    pub fn parse(s: &str) -> Result<Self, Box<dyn Error>> {
+        match s {
+            "clash" => Ok(Self::Clash),
+            "sing-box" => Ok(Self::SingBox),
+            "xray" => Ok(Self::Xray),
+            others => Err(format!("invalid export format: {others}").into()),
+        }
+    }
+
+    // 节点列表所在的字段
+    fn nodes_key(&self) -> &str {
+        match self {
+            Self::Clash => "proxies",
+            Self::SingBox | Self::Xray => "outbounds",
+        }
+    }
+
+    // 节点名称所在的字段
+    fn name_key(&self) -> &str {
+        match self {
+            Self::Clash => "name",
+            Self::SingBox | Self::Xray => "tag",
+        }
+    }
+}
+
+// 把 value 中路径为 path 的字段设置为 x，中间缺少的对象会被创建
+fn set_path(value: &mut Value, path: &[&str], x: Value) {
+    let mut cur = value;
+    for key in &path[..path.len() - 1] {
+        if !cur[*key].is_object() {
+            cur[*key] = json!({});
+        }
+        cur = &mut cur[*key];
+    }
+    cur[path[path.len() - 1]] = x;
+}
+
+// 只在字段已存在时设置
+fn set_if_exists(value: &mut Value, path: &[&str], x: Value) -> bool {
+    let mut cur = &*value;
+    for key in path {
+        match cur.get(*key) {
+            Some(v) => cur = v,
+            None => return false,
+        }
+    }
+    set_path(value, path, x);
+    true
+}
+
+// 根据占位节点生成使用 addr 的节点
+fn make_node(
+    format: Format,
+    placeholder: &Value,
+    name: &str,
+    addr: SocketAddr,
+    host: &str,
+) -> Value {
+    let mut node = placeholder.clone();
+    node[format.name_key()] = json!(name);
+    let ip = json!(addr.ip().to_string());
+    let port = json!(addr.port());
+    let host = json!(host);
+
+    match format {
+        Format::Clash => {
+            node["server"] = ip;
+            node["port"] = port;
+            let mut sni = set_if_exists(&mut node, &["sni"], host.clone());
+            sni |= set_if_exists(&mut node, &["servername"], host.clone());
+            if !sni {
+                node["servername"] = host.clone();
+            }
+            if node.get("ws-opts").is_some() {
+                set_path(&mut node, &["ws-opts", "headers", "Host"], host);
+            }
+        }
+        Format::SingBox => {
+            node["server"] = ip;
+            node["server_port"] = port;
+            set_path(&mut node, &["tls", "server_name"], host.clone());
+            if node.get("transport").is_some() {
+                set_path(&mut node, &["transport", "headers", "Host"], host);
+            }
+        }
+        Format::Xray => {
+            for key in ["vnext", "servers"] {
+                if let Some(servers) = node["settings"][key].as_array_mut() {
+                    servers.iter_mut().for_each(|x| {
+                        x["address"] = ip.clone();
+                        x["port"] = port.clone();
+                    });
+                }
+            }
+            // 只有 tls 和 reality 需要设置 serverName
+            let security = node["streamSettings"]["security"]
+                .as_str()
+                .unwrap_or_default();
+            if matches!(security, "tls" | "reality") {
+                let tls_key = format!("{security}Settings");
+                set_path(
+                    &mut node,
+                    &["streamSettings", tls_key.as_str(), "serverName"],
+                    host.clone(),
+                );
+            }
+            if node["streamSettings"].get("wsSettings").is_some() {
+                set_path(
+                    &mut node,
+                    &["streamSettings", "wsSettings", "headers", "Host"],
+                    host,
+                );
+            }
+        }
+    }
+    node
+}
+
+// 把分组成员中的占位节点替换为 names，没有占位节点时追加到末尾
+fn replace_members(members: &mut Vec<Value>, placeholder: &str, names: &[String]) {
+    let pos = members.iter().position(|x| x == &json!(placeholder));
+    members.retain(|x| x != &json!(placeholder));
+    let pos = pos.unwrap_or(members.len());
+    for (i, name) in names.iter().enumerate() {
+        members.insert(pos + i, json!(name));
+    }
+}
+
+// 把所有分组中的占位节点替换为 names，名为 group 的分组中没有占位节点时把 names 追加到末尾
+fn insert_group(
+    format: Format,
+    doc: &mut Value,
+    group: &str,
+    placeholder: &str,
+    names: &[String],
+) -> Result<(), Box<dyn Error>> {
+    let (list_key, group_key, members_key) = match format {
+        Format::Clash => ("proxy-groups", "name", "proxies"),
+        Format::SingBox => ("outbounds", "tag", "outbounds"),
+        Format::Xray => ("balancers", "tag", "selector"),
+    };
+
+    let list = match format {
+        Format::Xray => doc["routing"][list_key].as_array_mut(),
+        _ => doc[list_key].as_array_mut(),
+    };
+    let mut found = group.is_empty();
+    for x in list.into_iter().flatten() {
+        let is_target = !group.is_empty() && x[group_key] == json!(group);
+        match x[members_key].as_array_mut() {
+            Some(members) if is_target || members.contains(&json!(placeholder)) => {
+                replace_members(members, placeholder, names)
+            }
+            None if is_target => return Err(format!("group {group} has no {members_key}").into()),
+            _ => {}
+        }
+        found |= is_target;
+    }
+    if !found {
+        return Err(format!("group {group} not found in template").into());
+    }
+    Ok(())
+}
+
+// 用 addrs 替换模板中的占位节点，返回生成后的文档
+pub fn render(
+    format: Format,
+    template: &str,
+    placeholder: &str,
+    group: &str,
+    addrs: &[SocketAddr],
+    host: &str,
+) -> Result<String, Box<dyn Error>> {
+    let mut doc: Value = match format {
+        Format::Clash => serde_yaml::from_str(template)?,
+        Format::SingBox | Format::Xray => serde_json::from_str(template)?,
+    };
+
+    let nodes = doc[format.nodes_key()]
+        .as_array_mut()
+        .ok_or(format!("{} not found in template", format.nodes_key()))?;
+    let pos = nodes
+        .iter()
+        .position(|x| x[format.name_key()] == json!(placeholder))
+        .ok_or(format!(
+            "placeholder node {placeholder} not found in template"
+        ))?;
+    let placeholder_node = nodes.remove(pos);
+
+    let mut names = Vec::new();
+    for (i, addr) in addrs.iter().enumerate() {
+        let name = format!("{placeholder}-{}", i + 1);
+        nodes.insert(
+            pos + i,
+            make_node(format, &placeholder_node, &name, *addr, host),
+        );
+        names.push(name);
+    }
+
+    insert_group(format, &mut doc, group, placeholder, &names)?;
+
+    match format {
+        Format::Clash => Ok(serde_yaml::to_string(&doc)?),
+        Format::SingBox | Format::Xray => Ok(serde_json::to_string_pretty(&doc)?),
+    }
+}
+
+pub fn export(conf: &Config, result: &DownloadTestResult) -> Result<(), Box<dyn Error>> {
+    let addrs = result.top_addrs(conf.export.top.max(1));
+    if addrs.is_empty() {
+        println!("没有可用的下载测速数据，跳过导出");
+        return Ok(());
+    }
+
+    let host = Url::parse(&conf.url)?
+        .host_str()
+        .ok_or(format!("无法从 {} 中获取域名", conf.url))?
+        .to_string();
+    let format = Format::parse(&conf.export.format)?;
+    let template = fs::read_to_string(&conf.export.template)?;
+    let content = render(
+        format,
+        &template,
+        &conf.export.placeholder,
+        &conf.export.group,
+        &addrs,
+        &host,
+    )?;
+
+    fs::write(&conf.export.output, content)?;
+    println!("已导出 {} 个节点到 {}", addrs.len(), conf.export.output);
+    Ok(())
+}
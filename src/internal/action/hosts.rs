@@ -0,0 +1,130 @@
+use crate::internal::client::download::DownloadTestResult;
+use crate::internal::config::def::Config;
+
+use reqwest::Url;
+use std::error::Error;
+use std::fs;
+use std::net::IpAddr;
+
+pub const BLOCK_BEGIN: &str = "# >>> cf-proxy-test >>>";
+pub const BLOCK_END: &str = "# <<< cf-proxy-test <<<";
+
+// 需要写入 hosts 的域名，测试 URL 的域名排在第一位
+pub fn hostnames(conf: &Config) -> Vec<String> {
+    let mut names = Vec::new();
+    if let Ok(url) = Url::parse(&conf.url) {
+        if let Some(host) = url.host_str() {
+            names.push(host.to_string());
+        }
+    }
+
+    for name in &conf.hosts.names {
+        if !names.contains(name) {
+            names.push(name.clone());
+        }
+    }
+    names
+}
+
+// 用新的区块替换 content 中已有的区块，没有区块时追加到末尾
+// 区块缺少结束标记时返回错误，避免把标记之后的内容全部删除
+pub fn render(content: &str, ip: IpAddr, names: &[String]) -> Result<String, Box<dyn Error>> {
+    let mut block = vec![BLOCK_BEGIN.to_string()];
+    names
+        .iter()
+        .for_each(|name| block.push(format!("{ip}\t{name}")));
+    block.push(BLOCK_END.to_string());
+
+    let mut lines = Vec::new();
+    let mut in_block = false;
+    let mut replaced = false;
+    for line in content.lines() {
+        match line.trim() {
+            BLOCK_BEGIN => {
+                in_block = true;
+                if !replaced {
+                    lines.append(&mut block.clone());
+                    replaced = true;
+                }
+            }
+            BLOCK_END if in_block => in_block = false,
+            _ if in_block => (),
+            _ => lines.push(line.to_string()),
+        }
+    }
+
+    if in_block {
+        return Err(format!("hosts 文件中的 {BLOCK_BEGIN} 缺少结束标记 {BLOCK_END}").into());
+    }
+
+    if !replaced {
+        lines.append(&mut block);
+    }
+
+    let mut output = lines.join("\n");
+    output.push('\n');
+    Ok(output)
+}
+
+// 逐行比较新旧内容，只显示首尾相同部分之间的改动
+pub fn diff(old: &str, new: &str) -> String {
+    let old: Vec<&str> = old.lines().collect();
+    let new: Vec<&str> = new.lines().collect();
+
+    let prefix = old.iter().zip(&new).take_while(|(x, y)| x == y).count();
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(x, y)| x == y)
+        .count();
+
+    let mut content = String::new();
+    old[prefix..old.len() - suffix]
+        .iter()
+        .for_each(|x| content.push_str(format!("-{x}\n").as_str()));
+    new[prefix..new.len() - suffix]
+        .iter()
+        .for_each(|x| content.push_str(format!("+{x}\n").as_str()));
+    content
+}
+
+pub fn update(conf: &Config, result: &DownloadTestResult) -> Result<(), Box<dyn Error>> {
+    let ip = match result.best_ip() {
+        Some(ip) => ip,
+        None => {
+            println!("没有可用的下载测速数据，跳过 hosts 更新");
+            return Ok(());
+        }
+    };
+
+    let names = hostnames(conf);
+    if names.is_empty() {
+        return Err(format!("无法从 {} 中获取域名", conf.url).into());
+    }
+
+    let path = conf.hosts.path.as_str();
+    let old = match fs::read_to_string(path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+        Err(e) => return Err(Box::new(e)),
+    };
+    let new = render(&old, ip, &names)?;
+
+    if old == new {
+        println!("hosts 文件 {path} 无需更新");
+        return Ok(());
+    }
+
+    if conf.hosts.dry_run {
+        println!("hosts 文件 {path} 将做以下改动：\n{}", diff(&old, &new));
+        return Ok(());
+    }
+
+    if conf.hosts.backup && !old.is_empty() {
+        fs::write(format!("{path}.bak"), &old)?;
+    }
+    fs::write(path, new)?;
+    println!("已将 {ip} 写入 hosts 文件 {path}");
+    Ok(())
+}
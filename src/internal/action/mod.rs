@@ -0,0 +1,4 @@
+pub mod dns;
+pub mod export;
+pub mod hosts;
+pub mod notify;
@@ -0,0 +1,114 @@
+pub mod smtp;
+pub mod telegram;
+pub mod webhook;
+
+use crate::internal::client::summary::RunSummary;
+use crate::internal::config::def::Config;
+
+use async_trait::async_trait;
+use std::error::Error;
+use std::time::Duration;
+use tokio::runtime::Runtime;
+
+use smtp::SmtpNotifier;
+use telegram::TelegramNotifier;
+use webhook::WebhookNotifier;
+
+// 两次重试之间的等待时间
+const RETRY_INTERVAL: Duration = Duration::from_secs(1);
+
+#[async_trait]
+pub trait Notifier {
+    async fn send(&self, summary: &RunSummary) -> Result<(), Box<dyn Error>>;
+
+    fn get_name(&self) -> &str;
+    fn get_retries(&self) -> usize;
+}
+
+// 把 json 字符串中需要转义的字符转义，返回不带引号的内容
+fn json_escape(s: &str) -> String {
+    let quoted = serde_json::to_string(s).unwrap_or_default();
+    quoted[1..quoted.len() - 1].to_string()
+}
+
+// 替换模板中的 {{name}} 占位符，替换的值按 json 字符串转义
+pub fn render_template(template: &str, summary: &RunSummary) -> String {
+    let best_ip = summary.best_ip.map(|x| x.to_string()).unwrap_or_default();
+    let vars = [
+        ("time", summary.time.to_string()),
+        ("best_ip", best_ip),
+        (
+            "best_port",
+            summary.best_port.map(|x| x.to_string()).unwrap_or_default(),
+        ),
+        ("colo", summary.colo.clone().unwrap_or_default()),
+        ("latency", summary.latency_text()),
+        ("latency_delta", summary.latency_delta_text()),
+        ("speed", summary.speed_text()),
+        ("speed_delta", summary.speed_delta_text()),
+        ("text", summary.to_string()),
+    ];
+
+    let mut content = template.to_string();
+    for (name, value) in vars {
+        content = content.replace(format!("{{{{{name}}}}}").as_str(), &json_escape(&value));
+    }
+    content
+}
+
+pub fn create_notifiers(conf: &Config) -> Vec<Box<dyn Notifier>> {
+    let mut notifiers: Vec<Box<dyn Notifier>> = Vec::new();
+    let c = &conf.notify;
+    if c.webhook.enable {
+        notifiers.push(Box::new(WebhookNotifier::build(&c.webhook)));
+    }
+    if c.telegram.enable {
+        notifiers.push(Box::new(TelegramNotifier::build(&c.telegram)));
+    }
+    if c.smtp.enable {
+        notifiers.push(Box::new(SmtpNotifier::build(&c.smtp)));
+    }
+    notifiers
+}
+
+pub async fn send_with_retry(
+    notifier: &dyn Notifier,
+    summary: &RunSummary,
+) -> Result<(), Box<dyn Error>> {
+    let mut attempt = 0;
+    loop {
+        match notifier.send(summary).await {
+            Ok(()) => return Ok(()),
+            Err(e) if attempt >= notifier.get_retries() => return Err(e),
+            Err(e) => {
+                attempt += 1;
+                println!(
+                    "{} 通知发送失败: {e}，第 {attempt} 次重试",
+                    notifier.get_name()
+                );
+                tokio::time::sleep(RETRY_INTERVAL).await;
+            }
+        }
+    }
+}
+
+pub fn notify(conf: &Config, summary: &RunSummary) {
+    let notifiers = create_notifiers(conf);
+    if notifiers.is_empty() {
+        return;
+    }
+
+    let rt = match Runtime::new() {
+        Ok(rt) => rt,
+        Err(e) => {
+            println!("发送通知失败: {e}");
+            return;
+        }
+    };
+    for notifier in notifiers {
+        match rt.block_on(send_with_retry(notifier.as_ref(), summary)) {
+            Ok(()) => println!("已发送 {} 通知", notifier.get_name()),
+            Err(e) => println!("{} 通知发送失败: {e}", notifier.get_name()),
+        }
+    }
+}
@@ -0,0 +1,208 @@
+use super::Notifier;
+use crate::internal::client::summary::RunSummary;
+use crate::internal::config::def::SmtpConfig;
+
+use async_trait::async_trait;
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use std::error::Error;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+use tokio::time;
+
+// 明文 SMTP，不支持 STARTTLS，一般用于本地或内网的邮件中继
+pub struct SmtpNotifier {
+    server: String,
+    username: String,
+    password: String,
+    from: String,
+    to: Vec<String>,
+    subject: String,
+    timeout: Duration,
+    retries: usize,
+}
+
+impl SmtpNotifier {
+    pub fn build(conf: &SmtpConfig) -> Self {
+        Self {
+            server: conf.server.clone(),
+            username: conf.username.clone(),
+            password: conf.password.clone(),
+            from: conf.from.clone(),
+            to: conf.to.clone(),
+            subject: conf.subject.clone(),
+            timeout: Duration::from_secs(conf.timeout),
+            retries: conf.retries,
+        }
+    }
+
+    async fn session(&self, summary: &RunSummary) -> Result<(), Box<dyn Error>> {
+        let stream = TcpStream::connect(self.server.as_str()).await?;
+        let (reader, mut writer) = stream.into_split();
+        let mut reader = BufReader::new(reader);
+
+        expect(&mut reader, "220").await?;
+        command(&mut writer, &mut reader, "EHLO cf-proxy-test", "250").await?;
+
+        if !self.username.is_empty() {
+            let auth = STANDARD.encode(format!("\0{}\0{}", self.username, self.password));
+            command(
+                &mut writer,
+                &mut reader,
+                &format!("AUTH PLAIN {auth}"),
+                "235",
+            )
+            .await?;
+        }
+
+        command(
+            &mut writer,
+            &mut reader,
+            &format!("MAIL FROM:<{}>", self.from),
+            "250",
+        )
+        .await?;
+        for to in &self.to {
+            command(&mut writer, &mut reader, &format!("RCPT TO:<{to}>"), "250").await?;
+        }
+        command(&mut writer, &mut reader, "DATA", "354").await?;
+
+        let body = dot_stuff(&summary.to_string());
+        let now = SystemTime::now().duration_since(UNIX_EPOCH)?;
+        let domain = self.from.rsplit_once('@').map_or("cf-proxy-test", |x| x.1);
+        let message = format!(
+            "Date: {}\r\nMessage-ID: <{}.{}.{}@{domain}>\r\nFrom: <{}>\r\nTo: {}\r\nSubject: {}\r\nMIME-Version: 1.0\r\nContent-Type: text/plain; charset=utf-8\r\nContent-Transfer-Encoding: 8bit\r\n\r\n{}\r\n.",
+            format_date(now.as_secs()),
+            now.as_secs(),
+            now.subsec_nanos(),
+            std::process::id(),
+            self.from,
+            self.to
+                .iter()
+                .map(|x| format!("<{x}>"))
+                .collect::<Vec<String>>()
+                .join(", "),
+            encode_header(&self.subject),
+            body.replace('\n', "\r\n"),
+        );
+        command(&mut writer, &mut reader, &message, "250").await?;
+        command(&mut writer, &mut reader, "QUIT", "221").await?;
+        Ok(())
+    }
+}
+
+// 按 RFC 5322 格式化 unix 时间戳（UTC），如 Thu, 01 Jan 1970 00:00:00 +0000
+pub fn format_date(secs: u64) -> String {
+    const WEEKDAYS: [&str; 7] = ["Thu", "Fri", "Sat", "Sun", "Mon", "Tue", "Wed"];
+    const MONTHS: [&str; 12] = [
+        "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+    ];
+    let days = (secs / 86400) as i64;
+    let rest = secs % 86400;
+
+    // 按公历从天数计算年月日
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    format!(
+        "{}, {:02} {} {} {:02}:{:02}:{:02} +0000",
+        WEEKDAYS[(days % 7) as usize],
+        day,
+        MONTHS[(month - 1) as usize],
+        year,
+        rest / 3600,
+        rest % 3600 / 60,
+        rest % 60
+    )
+}
+
+// 按 RFC 2047 编码包含非 ASCII 字符的邮件头，每个 encoded-word 不超过 75 个字符
+pub fn encode_header(text: &str) -> String {
+    if text.is_ascii() {
+        return text.to_string();
+    }
+
+    let mut words = Vec::new();
+    let mut chunk = String::new();
+    for c in text.chars() {
+        // 45 字节编码后为 60 个字符，加上 =?UTF-8?B? 和 ?= 共 72 个字符
+        if chunk.len() + c.len_utf8() > 45 {
+            words.push(format!("=?UTF-8?B?{}?=", STANDARD.encode(&chunk)));
+            chunk.clear();
+        }
+        chunk.push(c);
+    }
+    words.push(format!("=?UTF-8?B?{}?=", STANDARD.encode(&chunk)));
+    words.join("\r\n ")
+}
+
+// 以 . 开头的行需要再加一个 .，避免被误认为 DATA 的结束标记
+pub fn dot_stuff(body: &str) -> String {
+    body.split('\n')
+        .map(|line| {
+            if line.starts_with('.') {
+                format!(".{line}")
+            } else {
+                line.to_string()
+            }
+        })
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+// 读取一个可能有多行的响应，检查响应码
+async fn expect<R>(reader: &mut BufReader<R>, code: &str) -> Result<(), Box<dyn Error>>
+where
+    R: tokio::io::AsyncRead + Unpin,
+{
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line).await? == 0 {
+            return Err("smtp connection closed".into());
+        }
+        if !line.starts_with(code) {
+            return Err(format!("smtp error: {}", line.trim_end()).into());
+        }
+        // 250-xxx 表示后面还有响应行
+        if line.as_bytes().get(3) != Some(&b'-') {
+            return Ok(());
+        }
+    }
+}
+
+async fn command<R, W>(
+    writer: &mut W,
+    reader: &mut BufReader<R>,
+    line: &str,
+    code: &str,
+) -> Result<(), Box<dyn Error>>
+where
+    R: tokio::io::AsyncRead + Unpin,
+    W: tokio::io::AsyncWrite + Unpin,
+{
+    writer.write_all(format!("{line}\r\n").as_bytes()).await?;
+    expect(reader, code).await
+}
+
+#[async_trait]
+impl Notifier for SmtpNotifier {
+    async fn send(&self, summary: &RunSummary) -> Result<(), Box<dyn Error>> {
+        time::timeout(self.timeout, self.session(summary)).await?
+    }
+
+    fn get_name(&self) -> &str {
+        "smtp"
+    }
+
+    fn get_retries(&self) -> usize {
+        self.retries
+    }
+}
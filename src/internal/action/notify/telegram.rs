@@ -0,0 +1,59 @@
+use super::Notifier;
+use crate::internal::client::summary::RunSummary;
+use crate::internal::config::def::TelegramConfig;
+
+use async_trait::async_trait;
+use serde_json::json;
+use std::error::Error;
+use std::time::Duration;
+
+pub struct TelegramNotifier {
+    base_url: String,
+    token: String,
+    chat_id: String,
+    timeout: Duration,
+    retries: usize,
+}
+
+impl TelegramNotifier {
+    pub fn build(conf: &TelegramConfig) -> Self {
+        Self {
+            base_url: conf.base_url.trim_end_matches('/').to_string(),
+            token: conf.token.clone(),
+            chat_id: conf.chat_id.clone(),
+            timeout: Duration::from_secs(conf.timeout),
+            retries: conf.retries,
+        }
+    }
+}
+
+#[async_trait]
+impl Notifier for TelegramNotifier {
+    async fn send(&self, summary: &RunSummary) -> Result<(), Box<dyn Error>> {
+        let client = reqwest::Client::builder().timeout(self.timeout).build()?;
+        let url = format!("{}/bot{}/sendMessage", self.base_url, self.token);
+        let body = json!({
+            "chat_id": self.chat_id,
+            "text": format!("cf-proxy-test 测速完成\n{summary}"),
+        });
+        let resp = client
+            .post(url)
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .body(body.to_string())
+            .send()
+            .await?;
+
+        if !resp.status().is_success() {
+            return Err(format!("status {}", resp.status()).into());
+        }
+        Ok(())
+    }
+
+    fn get_name(&self) -> &str {
+        "telegram"
+    }
+
+    fn get_retries(&self) -> usize {
+        self.retries
+    }
+}
@@ -0,0 +1,56 @@
+use super::{render_template, Notifier};
+use crate::internal::client::summary::RunSummary;
+use crate::internal::config::def::WebhookConfig;
+
+use async_trait::async_trait;
+use reqwest::Method;
+use std::error::Error;
+use std::str::FromStr;
+use std::time::Duration;
+
+pub struct WebhookNotifier {
+    url: String,
+    method: String,
+    template: String,
+    timeout: Duration,
+    retries: usize,
+}
+
+impl WebhookNotifier {
+    pub fn build(conf: &WebhookConfig) -> Self {
+        Self {
+            url: conf.url.clone(),
+            method: conf.method.clone(),
+            template: conf.template.clone(),
+            timeout: Duration::from_secs(conf.timeout),
+            retries: conf.retries,
+        }
+    }
+}
+
+#[async_trait]
+impl Notifier for WebhookNotifier {
+    async fn send(&self, summary: &RunSummary) -> Result<(), Box<dyn Error>> {
+        let client = reqwest::Client::builder().timeout(self.timeout).build()?;
+        let method = Method::from_str(&self.method.to_uppercase())?;
+        let resp = client
+            .request(method, self.url.as_str())
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .body(render_template(&self.template, summary))
+            .send()
+            .await?;
+
+        if !resp.status().is_success() {
+            return Err(format!("status {}", resp.status()).into());
+        }
+        Ok(())
+    }
+
+    fn get_name(&self) -> &str {
+        "webhook"
+    }
+
+    fn get_retries(&self) -> usize {
+        self.retries
+    }
+}
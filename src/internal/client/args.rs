@@ -1,6 +1,6 @@
 extern crate clap;
 
-use clap::{arg, Arg};
+use clap::{arg, Arg, ArgAction, ArgMatches};
 
 pub const DEFAULT_CONF: &str = "./conf.yaml";
 pub const DEFAULT_IP_FILE: &str = "./ip.txt";
@@ -9,8 +9,75 @@ pub const DEFAULT_DOWNLOAD_TIMEOUT: u64 = 10;
 
 fn register_args() -> Vec<Arg> {
     vec![
-        arg!(-c --config <CONFIG> "指定配置文件，默认为 ./conf.yaml"),
-        arg!(-s --src <IP_FILE_SOURCE> "指定 ip 文件，默认为 ./ip.txt"),
+        arg!(-c --config <CONFIG> "指定配置文件，默认为 ./conf.yaml").global(true),
+        arg!(-s --src <IP_FILE_SOURCE> "指定 ip 来源，可以是文件、目录或 -（标准输入），可多次指定，默认为 ./ip.txt")
+            .action(ArgAction::Append)
+            .global(true),
+        arg!(--"src-top" <N> "-s 指定上次的结果文件（.json/.csv）时，只取排名前 N 的 ip")
+            .value_parser(clap::value_parser!(usize))
+            .global(true),
+        arg!(--"src-by" <COLUMN> "结果文件的排序列，可选 speed, score, latency，默认保持文件中的顺序")
+            .value_parser(["speed", "score", "latency"])
+            .global(true),
+        arg!(-d --daemon "以守护模式运行，与 daemon 子命令相同"),
+    ]
+}
+
+// 选择测速阶段的参数，用于 test 子命令和不带子命令时
+fn phase_args() -> Vec<Arg> {
+    vec![
+        arg!(--"no-download" "只测试连通性，不测试下载速度"),
+        arg!(--"download-only" "跳过连通性测试，直接测试 ip 来源中地址的下载速度")
+            .conflicts_with("no-download"),
+        arg!(--phases <PHASES> "测速的阶段，逗号分隔，可选 conn, download, upload，默认使用配置文件中的 phases")
+            .value_parser(check_phases)
+            .conflicts_with_all(["no-download", "download-only"]),
+    ]
+}
+
+// 只接受已知的阶段，上传测试使用下载测试的结果，需要同时指定 download
+fn check_phases(s: &str) -> Result<String, String> {
+    let phases: Vec<&str> = s.split(',').map(|x| x.trim()).collect();
+    if let Some(x) = phases
+        .iter()
+        .find(|x| !["conn", "download", "upload"].contains(x))
+    {
+        return Err(format!("invalid phase: {x}"));
+    }
+    if phases.contains(&"upload") && !phases.contains(&"download") {
+        return Err("upload 需要 download 的结果，需要同时指定 download".to_string());
+    }
+    Ok(s.to_string())
+}
+
+fn register_subcommands() -> Vec<clap::Command> {
+    vec![
+        clap::Command::new("test")
+            .about("测试连通性和下载速度，然后执行已启用的后续动作，不带子命令时的默认行为")
+            .args(phase_args()),
+        clap::Command::new("scan")
+            .about("只测试连通性，适合测试大量网段")
+            .arg(arg!(-m --method <METHOD> "连通性测试方式，可选 http, tcp, quic，默认使用配置文件中的 conn.method")),
+        clap::Command::new("bench").about("跳过连通性测试，直接测试 ip 来源中地址的下载速度"),
+        clap::Command::new("history")
+            .about("显示测速历史，需要配置 history.path")
+            .arg(
+                arg!(-n --limit <LIMIT> "显示最近的记录数量")
+                    .value_parser(clap::value_parser!(usize))
+                    .default_value("10"),
+            ),
+        clap::Command::new("daemon").about("以守护模式运行，定时测速并提供 HTTP API"),
+        clap::Command::new("export")
+            .about("测速后按 export 配置导出代理客户端配置，不需要设置 export.enable"),
+        clap::Command::new("config")
+            .about("配置文件相关操作")
+            .subcommand_required(true)
+            .subcommand(
+                clap::Command::new("init")
+                    .about("在 -c 指定的位置生成带注释的默认配置文件")
+                    .arg(arg!(-f --force "覆盖已存在的文件")),
+            )
+            .subcommand(clap::Command::new("check").about("检查配置文件是否有效")),
     ]
 }
 
@@ -18,27 +85,111 @@ pub fn new_cmd() -> clap::Command {
     clap::Command::new("cf-proxy-test")
         .about("用于测试 Cloudflare 反代IP，仅供学习或者娱乐使用。")
         .args(register_args())
+        .args(phase_args())
+        .subcommands(register_subcommands())
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Action {
+    Test,
+    Scan,
+    Bench,
+    // 显示的记录数量
+    History(usize),
+    Daemon,
+    Export,
+    // 是否覆盖已存在的文件
+    ConfigInit(bool),
+    ConfigCheck,
 }
 
 pub struct Command {
     pub conf_path: String,
-    pub ip_src: String,
+    pub ip_src: Vec<String>,
+    // 应用于 ip_src 中的结果文件
+    pub src_top: usize,
+    pub src_by: String,
+    pub action: Action,
+    // 命令行指定的阶段，代替配置文件中的 phases
+    pub phases: Option<Vec<String>>,
+    // 命令行指定的连通性测试方式，代替配置文件中的 conn.method
+    pub method: Option<String>,
+}
+
+fn phases_of(cmd: &ArgMatches) -> Option<Vec<String>> {
+    if cmd.get_flag("no-download") {
+        return Some(vec!["conn".to_string()]);
+    }
+    if cmd.get_flag("download-only") {
+        return Some(vec!["download".to_string()]);
+    }
+    cmd.get_one::<String>("phases")
+        .map(|x| x.split(',').map(|x| x.trim().to_string()).collect())
 }
 
 impl Command {
     pub fn init() -> Self {
-        let cmd = new_cmd().get_matches();
+        Self::parse(new_cmd().get_matches())
+    }
+
+    pub fn parse(cmd: ArgMatches) -> Self {
+        let mut phases = None;
+        let mut method = None;
+        // 全局参数只会传递给子命令，需要从最内层的子命令中读取
+        let (action, matches) = match cmd.subcommand() {
+            Some(("test", m)) => {
+                phases = phases_of(m);
+                (Action::Test, m)
+            }
+            Some(("scan", m)) => {
+                phases = Some(vec!["conn".to_string()]);
+                method = m.get_one::<String>("method").cloned();
+                (Action::Scan, m)
+            }
+            Some(("bench", m)) => {
+                phases = Some(vec!["download".to_string()]);
+                (Action::Bench, m)
+            }
+            Some(("history", m)) => (Action::History(*m.get_one::<usize>("limit").unwrap()), m),
+            Some(("daemon", m)) => (Action::Daemon, m),
+            Some(("export", m)) => (Action::Export, m),
+            Some(("config", m)) => match m.subcommand() {
+                Some(("init", m)) => (Action::ConfigInit(m.get_flag("force")), m),
+                Some(("check", m)) => (Action::ConfigCheck, m),
+                _ => unreachable!("config 需要子命令"),
+            },
+            _ if cmd.get_flag("daemon") => (Action::Daemon, &cmd),
+            _ => {
+                phases = phases_of(&cmd);
+                (Action::Test, &cmd)
+            }
+        };
 
         let mut conf_path = DEFAULT_CONF.to_string();
-        if let Some(p) = cmd.get_one::<String>("config") {
+        if let Some(p) = matches.get_one::<String>("config") {
             conf_path = p.to_owned();
         }
 
-        let mut ip_src = DEFAULT_IP_FILE.to_string();
-        if let Some(p) = cmd.get_one::<String>("src") {
-            ip_src = p.to_owned();
-        }
+        // 没有指定时在 Config::init 中使用配置文件中的来源或者 DEFAULT_IP_FILE
+        let ip_src = matches
+            .get_many::<String>("src")
+            .map(|x| x.cloned().collect())
+            .unwrap_or_default();
 
-        Self { conf_path, ip_src }
+        Self {
+            conf_path,
+            ip_src,
+            src_top: matches
+                .get_one::<usize>("src-top")
+                .copied()
+                .unwrap_or_default(),
+            src_by: matches
+                .get_one::<String>("src-by")
+                .cloned()
+                .unwrap_or_default(),
+            action,
+            phases,
+            method,
+        }
     }
 }
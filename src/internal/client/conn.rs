@@ -1,26 +1,82 @@
 use super::def::ServerAddress;
+use super::retry::RetryPolicy;
+use crate::internal::source::IpList;
 use async_trait::async_trait;
 use futures::future::join_all;
 use std::error::Error;
 use std::fmt::{ Display};
-use std::{net::IpAddr, time::Duration};
+use std::collections::HashSet;
+use std::net::{IpAddr, SocketAddr};
+use std::time::Duration;
 use tokio::runtime::Runtime;
 
 #[derive(Clone)]
 pub struct ConnectTestStats {
     pub ip: IpAddr,
+    pub port: u16,
+    // 多次测试时为成功的平均耗时
     pub cost: Duration,
+    // 多次测试时失败的比例
+    pub loss: f64,
+    // 多次测试时相邻两次耗时之差的平均值
+    pub jitter: Duration,
+    // 按重试策略重试的总次数，有重试的地址排在没有重试的地址之后
+    pub retries: usize,
+    // Cloudflare 数据中心，从 cf-ray 响应头中获取
+    pub colo: Option<String>,
+    // 实际使用的协议，如 HTTP/1.1、HTTP/2.0，QUIC 探测时为 QUIC v1
+    pub protocol: Option<String>,
+    // protocol 为 http3 时连通性测试之后进行的 QUIC 探测耗时，None 表示没有探测，Some(None) 表示没有响应
+    pub quic: Option<Option<Duration>>,
+    // ip 所属的来源标签
+    pub tags: Vec<String>,
 }
 
 impl ConnectTestStats {
-    pub fn new(ip: IpAddr, cost: Duration) -> Self {
-        Self { ip, cost }
+    pub fn new(addr: SocketAddr, cost: Duration) -> Self {
+        Self {
+            ip: addr.ip(),
+            port: addr.port(),
+            cost,
+            loss: 0.0,
+            jitter: Duration::ZERO,
+            retries: 0,
+            colo: None,
+            protocol: None,
+            quic: None,
+            tags: Vec::new(),
+        }
+    }
+
+    pub fn addr(&self) -> SocketAddr {
+        SocketAddr::new(self.ip, self.port)
     }
 }
 
 impl Display for ConnectTestStats {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "连接 {:<15} 耗时 {:?}", self.ip, self.cost)
+        write!(f, "连接 {:<21} 耗时 {:?}", self.addr().to_string(), self.cost)?;
+        if self.loss > 0.0 || !self.jitter.is_zero() {
+            write!(f, " 丢包 {:.0}% 抖动 {:?}", self.loss * 100.0, self.jitter)?;
+        }
+        if self.retries > 0 {
+            write!(f, " 重试 {} 次", self.retries)?;
+        }
+        if let Some(colo) = &self.colo {
+            write!(f, " 数据中心 {colo}")?;
+        }
+        if let Some(protocol) = &self.protocol {
+            write!(f, " 协议 {protocol}")?;
+        }
+        match &self.quic {
+            Some(Some(cost)) => write!(f, " QUIC {cost:?}")?,
+            Some(None) => write!(f, " QUIC 无响应")?,
+            None => (),
+        }
+        if !self.tags.is_empty() {
+            write!(f, " 来源 {}", self.tags.join(","))?;
+        }
+        Ok(())
     }
 }
 
@@ -28,6 +84,8 @@ impl Display for ConnectTestStats {
 pub struct ConnectTestResult {
     top: usize,
     list: Option<Vec<ConnectTestStats>>,
+    // 连接失败的地址
+    failed: Vec<SocketAddr>,
 }
 
 
@@ -49,6 +107,15 @@ impl Display for ConnectTestResult {
                     true
                 });
 
+                // 测试了多个端口时，显示每个 IP 最快的端口
+                let best_ports = self.best_ports();
+                if best_ports.len() < list.len() {
+                    content.push_str(format!("下面是连接速度最快的 {} 个 IP 及其最快的端口：\n", self.top).as_str());
+                    best_ports.iter().take(self.top).for_each(|x| {
+                        content.push_str(format!("{:<15} 端口 {:<5} 耗时 {:?}\n", x.ip, x.port, x.cost).as_str());
+                    });
+                }
+
                 write!(f, "{}", content)
             }
         }
@@ -56,13 +123,105 @@ impl Display for ConnectTestResult {
 }
 
 impl ConnectTestResult {
-    pub fn top_ips(&self) -> Vec<IpAddr>{
-        let mut ips = Vec::new();
-        if let Some(list) = &self.list{
-            list.iter().for_each(|x|{ ips.push(x.ip) });
+    // 跳过连通性测试时使用的空结果
+    pub fn empty(top: usize) -> Self {
+        Self {
+            top,
+            list: None,
+            failed: Vec::new(),
+        }
+    }
+
+    pub fn apply_tags(&mut self, list: &IpList) {
+        if let Some(stats) = &mut self.list {
+            stats.iter_mut().for_each(|x| x.tags = list.tags_of(&x.ip));
+        }
+    }
+
+    pub fn failed(&self) -> &[SocketAddr] {
+        &self.failed
+    }
+
+    pub fn list(&self) -> &[ConnectTestStats] {
+        match &self.list {
+            Some(list) => list,
+            None => &[],
+        }
+    }
+
+    pub fn get(&self, addr: SocketAddr) -> Option<&ConnectTestStats> {
+        self.list.as_ref()?.iter().find(|x| x.addr() == addr)
+    }
+
+    pub fn top_addrs(&self) -> Vec<SocketAddr> {
+        self.list().iter().map(|x| x.addr()).collect()
+    }
+
+    // 合并另一次测试的结果，按连接耗时重新排序
+    pub fn merge(&mut self, other: ConnectTestResult) {
+        let mut list = self.list.take().unwrap_or_default();
+        list.extend(other.list.unwrap_or_default());
+        list.sort_by_key(|a| a.cost);
+        if !list.is_empty() {
+            self.list = Some(list);
+        }
+        self.failed.extend(other.failed);
+    }
+
+    // 记录 QUIC 探测的结果，quic 中没有的地址视为没有响应
+    pub fn set_quic(&mut self, quic: &ConnectTestResult) {
+        if let Some(list) = &mut self.list {
+            list.iter_mut()
+                .for_each(|x| x.quic = Some(quic.get(x.addr()).map(|q| q.cost)));
+        }
+    }
+
+    // 每个 IP 连接最快的端口，按连接耗时排序
+    pub fn best_ports(&self) -> Vec<&ConnectTestStats> {
+        let mut seen = HashSet::new();
+        self.list().iter().filter(|x| seen.insert(x.ip)).collect()
+    }
+}
+
+// 对每个地址测试 get_pings 次，每次失败时按 get_retry 重试，全部失败时视为连接失败
+async fn connect_times<T: ConnTest + ?Sized>(
+    client: &T,
+    dst: ServerAddress,
+    via: Option<ServerAddress>,
+    timeout: Duration,
+) -> Result<ConnectTestStats, Box<dyn Error>> {
+    let pings = client.get_pings().max(1);
+    let retry = client.get_retry();
+    let mut costs = Vec::new();
+    let mut first = None;
+    let mut last_err = None;
+    let mut retries = 0;
+    for _ in 0..pings {
+        let (result, n) = retry
+            .run(|| client.connect(dst.clone(), via.clone(), timeout))
+            .await;
+        retries += n;
+        match result {
+            Ok(x) => {
+                costs.push(x.cost);
+                first.get_or_insert(x);
+            }
+            Err(e) => last_err = Some(e),
         }
-        ips
     }
+
+    let mut stats = match first {
+        Some(x) => x,
+        None => return Err(last_err.unwrap()),
+    };
+    stats.cost = costs.iter().sum::<Duration>() / costs.len() as u32;
+    stats.loss = 1.0 - costs.len() as f64 / pings as f64;
+    stats.retries = retries;
+    if costs.len() > 1 {
+        let diffs: Duration = costs.windows(2).map(|x| x[0].abs_diff(x[1])).sum();
+        stats.jitter = diffs / (costs.len() - 1) as u32;
+    }
+    Ok(stats)
 }
 
 #[async_trait]
@@ -79,39 +238,60 @@ pub trait ConnTest {
         let addr_remote = self.get_address_remote();
         let timeout = self.get_timeout();
         let mut futures = Vec::new();
+        let mut ips = Vec::new();
         for addr in addrs_conn {
-            let future = self.connect(addr, addr_remote.clone(), timeout);
+            if let ServerAddress::Socket(socket) = &addr {
+                ips.push(Some(*socket));
+            } else {
+                ips.push(None);
+            }
+            let future = connect_times(self, addr, addr_remote.clone(), timeout);
             futures.push(future);
         }
 
         let mut retain = Vec::new();
-        println!("开始测试连接速度，请等待 {:?}", timeout);
+        let mut failed = Vec::new();
+        println!(
+            "开始测试连接速度，请等待 {:?}",
+            timeout * self.get_pings().max(1) as u32
+        );
         Runtime::new().unwrap().block_on(async {
             let stats = join_all(futures).await;
-            stats.into_iter().for_each(|x| {
-                if let Ok(x) = x {
-                    retain.push(x)
-                }
+            stats.into_iter().zip(ips).for_each(|(x, ip)| match x {
+                Ok(x) => retain.push(x),
+                Err(_) => failed.extend(ip),
             });
 
-            retain.sort_by(|a, b| a.cost.cmp(&b.cost));
+            retain.sort_by_key(|a| a.cost);
         });
 
-        if retain.len() == 0 {
+        if retain.is_empty() {
             return ConnectTestResult {
                 list: None,
                 top: self.get_top(),
+                failed,
             };
         }
 
-        return ConnectTestResult {
+        ConnectTestResult {
             list: Some(retain),
             top: self.get_top(),
-        };
+            failed,
+        }
     }
 
     fn get_address_conn(&self) -> Vec<ServerAddress>;
     fn get_address_remote(&self) -> Option<ServerAddress>;
     fn get_timeout(&self) -> Duration;
     fn get_top(&self) -> usize;
+
+    // 每个地址的测试次数
+    fn get_pings(&self) -> usize {
+        1
+    }
+
+    // 连接失败时的重试策略
+    fn get_retry(&self) -> RetryPolicy {
+        RetryPolicy::default()
+    }
 }
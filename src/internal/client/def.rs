@@ -1,9 +1,23 @@
+use serde::{Deserialize, Serialize};
 use std::net::SocketAddr;
 
 use url::Url;
 
+#[allow(clippy::upper_case_acronyms)]
 #[derive(Clone, Debug)]
 pub enum ServerAddress {
     Socket(SocketAddr),
     URL(Url),
 }
+
+// 与代理地址握手得到的 TLS 信息
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct TlsInfo {
+    // 协商的 TLS 版本，如 TLSv1.3
+    pub version: String,
+    // 服务器证书的签发者
+    pub issuer: String,
+    // 服务器证书的过期时间和剩余天数
+    pub expires: String,
+    pub days_left: i32,
+}
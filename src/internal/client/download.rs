@@ -1,169 +1,291 @@
-use std::{fmt::Display, net::IpAddr, time::Duration};
+use super::conn::ConnectTestResult;
+use super::def::TlsInfo;
+use crate::internal::source::IpList;
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+use std::net::{IpAddr, SocketAddr};
+use std::{fmt::Display, time::Duration};
 
-const SPEED_MULTIPLE: usize = 1 << 10;
+// 速度按比特还是字节显示
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SpeedUnit {
+    // Mbps 等，运营商宣传的带宽使用这种单位
+    Bit,
+    // MB/s 等
+    Byte,
+}
 
-#[derive(Clone, Debug)]
-pub enum Speed {
-    Byte(f64),
-    KByte(f64),
-    MByte(f64),
-    GByte(f64),
+// 单位前缀使用 1000 还是 1024 的倍数
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SpeedPrefix {
+    // k, M, G, T
+    Si,
+    // Ki, Mi, Gi, Ti
+    Iec,
 }
 
-impl PartialEq for Speed{
-    fn eq(&self, other: &Self) -> bool {
-        let mut x = self.clone();
-        let mut y = other.clone();
-        x.byte();
-        y.byte();
-        match (x, y) {
-            (Self::Byte(l0), Self::Byte(r0)) => l0 == r0,
-            _ => false,
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SpeedFormat {
+    pub unit: SpeedUnit,
+    pub prefix: SpeedPrefix,
+}
+
+// 默认按字节、1024 的倍数显示，如 MiB/s
+impl Default for SpeedFormat {
+    fn default() -> Self {
+        Self {
+            unit: SpeedUnit::Byte,
+            prefix: SpeedPrefix::Iec,
         }
     }
 }
 
-impl PartialOrd for Speed {
-    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
-        let mut x = self.clone();
-        let mut y = other.clone();
-        x.byte();
-        y.byte();
-        match (x, y) {
-            (Self::Byte(l0), Self::Byte(r0)) => {
-                if l0 == r0 {
-                    return Some(std::cmp::Ordering::Equal);
-                }
+// 速度，内部统一保存为字节/秒
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Speed(f64);
 
-                if l0 > r0 {
-                    return Some(std::cmp::Ordering::Greater);
-                }
-
-                return Some(std::cmp::Ordering::Less);
-            },
-            _=> None
-        }
+// 使用 total_cmp 比较，即使出现 NaN 排序也不会 panic，NaN 视为负无穷排在最后
+impl PartialEq for Speed {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
     }
 }
 
-fn scale_up(x: &mut f64) {
-    *x = *x * SPEED_MULTIPLE as f64
+impl Eq for Speed {}
+
+impl PartialOrd for Speed {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
 }
 
-fn scale_down(x: &mut f64)  {
-    *x = *x / SPEED_MULTIPLE as f64
+impl Ord for Speed {
+    fn cmp(&self, other: &Self) -> Ordering {
+        let value = |x: f64| if x.is_nan() { f64::NEG_INFINITY } else { x };
+        value(self.0).total_cmp(&value(other.0))
+    }
 }
 
 impl Speed {
+    pub fn from_bytes(byte_per_second: f64) -> Self {
+        Speed(byte_per_second)
+    }
+
+    // 耗时为 0 时速度记为 0
     pub fn byte_per_second(byte: usize, cost: Duration) -> Self {
-        Speed::Byte(byte as f64 / cost.as_secs_f64())
+        if cost.is_zero() {
+            return Speed(0.0);
+        }
+        Speed(byte as f64 / cost.as_secs_f64())
     }
 
-    fn unit_up(&mut self){
-        match self {
-            Speed::Byte(s)=> *self = Speed::KByte(*s),
-            Speed::KByte(s)=> *self = Speed::MByte(*s),
-            Speed::MByte(s)=> *self = Speed::GByte(*s),
-            Speed::GByte(_)=> (),
-        };
+    pub fn byte_value(&self) -> f64 {
+        self.0
     }
 
-    fn unit_down(&mut self){
-        match self {
-            Speed::GByte(s)=> *self = Speed::MByte(*s),
-            Speed::MByte(s)=> *self = Speed::KByte(*s),
-            Speed::KByte(s)=> *self = Speed::Byte(*s),
-            Speed::Byte(_)=> (),
+    // 按 format 显示，自动选择合适的单位前缀
+    pub fn format(&self, format: SpeedFormat) -> String {
+        let (mut value, units) = match (format.unit, format.prefix) {
+            (SpeedUnit::Byte, SpeedPrefix::Iec) => {
+                (self.0, ["B/s", "KiB/s", "MiB/s", "GiB/s", "TiB/s"])
+            }
+            (SpeedUnit::Byte, SpeedPrefix::Si) => (self.0, ["B/s", "kB/s", "MB/s", "GB/s", "TB/s"]),
+            (SpeedUnit::Bit, SpeedPrefix::Iec) => (
+                self.0 * 8.0,
+                ["bit/s", "Kibit/s", "Mibit/s", "Gibit/s", "Tibit/s"],
+            ),
+            (SpeedUnit::Bit, SpeedPrefix::Si) => {
+                (self.0 * 8.0, ["bps", "kbps", "Mbps", "Gbps", "Tbps"])
+            }
+        };
+        let multiple = match format.prefix {
+            SpeedPrefix::Si => 1000.0,
+            SpeedPrefix::Iec => 1024.0,
         };
-    }
 
-    fn upper(&mut self)  {
-        match self {
-            Speed::GByte(_) => (),
-            Speed::Byte(s)|Speed::KByte(s)|Speed::MByte(s)=> {
-                scale_down(s);
-                self.unit_up();
-            },
-        }
-    }
-    
-    fn lower(&mut self)  {
-        match self {
-            Speed::Byte(_)=>(),
-            Speed::KByte(s)|Speed::MByte(s)|Speed::GByte(s)=> {
-                scale_up(s);
-                self.unit_down();
-            },
+        let mut unit = 0;
+        while value.abs() >= multiple && unit < units.len() - 1 {
+            value /= multiple;
+            unit += 1;
         }
+        format!("{:.2} {}", value, units[unit])
     }
+}
 
-    #[allow(dead_code)]
-    pub fn byte(&mut self)  {
-        match self {
-            Speed::Byte(_) => (),
-            Speed::KByte(_) => self.lower(),
-            Speed::MByte(_) => {self.lower();self.lower()},
-            Speed::GByte(_) => {self.lower();self.lower();self.lower()},
-        };
-    }
-
+// 下载过程中每个采样间隔的速度
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct SpeedSeries {
+    // 采样间隔（秒）
+    pub interval: f64,
+    // 预热阶段的采样数，不参与统计
+    pub warmup: usize,
+    // 每个间隔的下载速度（字节/秒）
+    pub samples: Vec<f64>,
+}
 
+// 由采样得到的速度统计（字节/秒）
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SpeedStability {
+    pub peak: f64,
+    pub mean: f64,
+    // 10% 分位，即 90% 的时间里速度不低于这个值
+    pub p10: f64,
+    // 变异系数（标准差 / 平均值），越小越稳定
+    pub cv: f64,
+}
 
-    #[allow(dead_code)]
-    pub fn kb(&mut self)  {
-        match self {
-            Speed::Byte(_) => self.upper(),
-            Speed::KByte(_) => (),
-            Speed::MByte(_) => self.lower(),
-            Speed::GByte(_) => {self.lower();self.lower()},
+impl SpeedSeries {
+    // 预热之后的采样，下载在预热阶段就结束时使用全部采样
+    fn measured(&self) -> &[f64] {
+        match self.samples.get(self.warmup..) {
+            Some(x) if !x.is_empty() => x,
+            _ => &self.samples,
         }
     }
 
-    pub fn mb(&mut self)  {
-        match self {
-            Speed::Byte(_) => {self.upper();self.upper()},
-            Speed::KByte(_) => self.upper(),
-            Speed::MByte(_) => (),
-            Speed::GByte(_) => self.lower(),
+    pub fn stability(&self) -> Option<SpeedStability> {
+        let mut samples = self.measured().to_vec();
+        if samples.is_empty() {
+            return None;
         }
-    }
+        samples.sort_by(|x, y| x.total_cmp(y));
 
-    #[allow(dead_code)]
-    pub fn gb(&mut self)  {
-        match self {
-            Speed::Byte(_) => {self.upper();self.upper();self.upper()},
-            Speed::KByte(_) => {self.upper();self.upper()},
-            Speed::MByte(_) => self.upper(),
-            Speed::GByte(_) => (),
-        }
+        let n = samples.len() as f64;
+        let mean = samples.iter().sum::<f64>() / n;
+        let variance = samples.iter().map(|x| (x - mean).powi(2)).sum::<f64>() / n;
+        let cv = match mean > 0.0 {
+            true => variance.sqrt() / mean,
+            false => 0.0,
+        };
+        Some(SpeedStability {
+            peak: samples[samples.len() - 1],
+            mean,
+            p10: samples[((n - 1.0) * 0.1).round() as usize],
+            cv,
+        })
     }
-}
 
-impl Display for Speed {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match self {
-            Self::Byte(b) => write!(f, "{:.4} Byte/s", b),
-            Self::KByte(b) => write!(f, "{:.4} KB/s", b),
-            Self::MByte(b) => write!(f, "{:.4} MB/s", b),
-            Self::GByte(b) => write!(f, "{:.4} GB/s", b),
+    // 按位置把多个连接的采样相加
+    pub fn merge(series: &[SpeedSeries]) -> Self {
+        let mut merged = SpeedSeries::default();
+        for x in series {
+            merged.interval = x.interval;
+            merged.warmup = merged.warmup.max(x.warmup);
+            if merged.samples.len() < x.samples.len() {
+                merged.samples.resize(x.samples.len(), 0.0);
+            }
+            merged
+                .samples
+                .iter_mut()
+                .zip(&x.samples)
+                .for_each(|(m, s)| *m += s);
         }
+        merged
     }
 }
 
 pub struct DownloadTestStats {
     pub ip: IpAddr,
+    pub port: u16,
     pub speed: Speed,
+    // 多连接测试时的单连接下载速度，此时 speed 为多连接的合计速度
+    pub single_speed: Option<Speed>,
+    // 按时间采样的下载速度
+    pub series: SpeedSeries,
+    // 下载前和下载时的 TCP 连接延迟（中位数）
+    pub idle_latency: Option<Duration>,
+    pub loaded_latency: Option<Duration>,
+    // 下载失败后按重试策略重试的次数
+    pub retries: usize,
+    // https 地址的 TLS 版本和证书信息
+    pub tls: Option<TlsInfo>,
+    // 排序使用的分数，由 rank 设置
+    pub score: Option<f64>,
+    // Cloudflare 数据中心，从 cf-ray 响应头中获取
+    pub colo: Option<String>,
+    // 实际使用的协议，如 HTTP/1.1、HTTP/2.0、HTTP/3
+    pub protocol: Option<String>,
+    // ip 所属的来源标签
+    pub tags: Vec<String>,
+    // 显示速度使用的格式
+    pub format: SpeedFormat,
 }
 
 impl Display for DownloadTestStats {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{:<15} 下载速度 {}", self.ip, self.speed)
+        write!(
+            f,
+            "{:<21} 下载速度 {}",
+            self.addr().to_string(),
+            self.speed.format(self.format)
+        )?;
+        if let Some(score) = self.score {
+            write!(f, " 得分 {score:.2}")?;
+        }
+        if let Some(single) = &self.single_speed {
+            write!(f, " 单连接 {}", single.format(self.format))?;
+        }
+        if let Some(x) = self.series.stability() {
+            write!(
+                f,
+                " 峰值 {} 10%分位 {} 波动 {:.1}%",
+                Speed::from_bytes(x.peak).format(self.format),
+                Speed::from_bytes(x.p10).format(self.format),
+                x.cv * 100.0
+            )?;
+        }
+        if let (Some(idle), Some(loaded)) = (self.idle_latency, self.loaded_latency) {
+            write!(f, " 空闲延迟 {idle:?} 负载延迟 {loaded:?}")?;
+            match loaded.checked_sub(idle) {
+                Some(delta) => write!(f, " (+{delta:?})")?,
+                None => write!(f, " (-{:?})", idle - loaded)?,
+            }
+        }
+        if self.retries > 0 {
+            write!(f, " 重试 {} 次", self.retries)?;
+        }
+        if let Some(tls) = &self.tls {
+            write!(
+                f,
+                " {} 证书签发者 {} 剩余 {} 天",
+                tls.version, tls.issuer, tls.days_left
+            )?;
+        }
+        if let Some(colo) = &self.colo {
+            write!(f, " 数据中心 {colo}")?;
+        }
+        if let Some(protocol) = &self.protocol {
+            write!(f, " 协议 {protocol}")?;
+        }
+        if !self.tags.is_empty() {
+            write!(f, " 来源 {}", self.tags.join(","))?;
+        }
+        Ok(())
     }
 }
 
 impl DownloadTestStats {
-    pub fn new(ip: IpAddr, speed: Speed) -> Self {
-        Self { ip, speed }
+    pub fn new(addr: SocketAddr, speed: Speed) -> Self {
+        Self {
+            ip: addr.ip(),
+            port: addr.port(),
+            speed,
+            single_speed: None,
+            series: SpeedSeries::default(),
+            idle_latency: None,
+            loaded_latency: None,
+            retries: 0,
+            tls: None,
+            score: None,
+            colo: None,
+            protocol: None,
+            tags: Vec::new(),
+            format: SpeedFormat::default(),
+        }
+    }
+
+    pub fn addr(&self) -> SocketAddr {
+        SocketAddr::new(self.ip, self.port)
     }
 }
 
@@ -174,6 +296,69 @@ pub trait DownloadTest {
 pub struct DownloadTestResult {
     pub top: usize,
     pub list: Option<Vec<DownloadTestStats>>,
+    // 下载失败的地址
+    pub failed: Vec<SocketAddr>,
+}
+
+impl DownloadTestResult {
+    // 跳过下载测试时按连通性测试的顺序生成结果，速度为 0
+    pub fn from_conn(conn: &ConnectTestResult, format: SpeedFormat) -> Self {
+        let list: Vec<_> = conn
+            .list()
+            .iter()
+            .map(|x| {
+                let mut stat = DownloadTestStats::new(x.addr(), Speed::default());
+                stat.colo = x.colo.clone();
+                stat.protocol = x.protocol.clone();
+                stat.format = format;
+                stat
+            })
+            .collect();
+        Self {
+            top: conn.list().len(),
+            list: if list.is_empty() { None } else { Some(list) },
+            failed: Vec::new(),
+        }
+    }
+
+    pub fn apply_tags(&mut self, list: &IpList) {
+        if let Some(stats) = &mut self.list {
+            stats.iter_mut().for_each(|x| x.tags = list.tags_of(&x.ip));
+        }
+    }
+
+    // 下载速度最快的 IP
+    pub fn best_ip(&self) -> Option<IpAddr> {
+        self.best().map(|x| x.ip)
+    }
+
+    pub fn best(&self) -> Option<&DownloadTestStats> {
+        self.list.as_ref()?.first()
+    }
+
+    // 下载速度最快的 n 个 IP，同一个 IP 的多个端口只算一次
+    pub fn top_ips(&self, n: usize) -> Vec<IpAddr> {
+        let mut ips = Vec::new();
+        for x in self.list.iter().flatten() {
+            if ips.len() >= n {
+                break;
+            }
+            if !ips.contains(&x.ip) {
+                ips.push(x.ip);
+            }
+        }
+        ips
+    }
+
+    // 下载速度最快的 n 个地址
+    pub fn top_addrs(&self, n: usize) -> Vec<SocketAddr> {
+        self.list
+            .iter()
+            .flatten()
+            .take(n)
+            .map(|x| x.addr())
+            .collect()
+    }
 }
 
 impl Display for DownloadTestResult {
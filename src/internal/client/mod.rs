@@ -2,3 +2,7 @@ pub mod args;
 pub mod conn;
 pub mod def;
 pub mod download;
+pub mod report;
+pub mod retry;
+pub mod summary;
+pub mod upload;
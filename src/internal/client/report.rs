@@ -0,0 +1,425 @@
+use super::conn::ConnectTestResult;
+use super::def::TlsInfo;
+use super::download::{DownloadTestResult, SpeedSeries, SpeedStability};
+use super::summary::RunSummary;
+use super::upload::UploadTestResult;
+
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::fs;
+use std::net::{IpAddr, SocketAddr};
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ConnReportEntry {
+    pub ip: IpAddr,
+    #[serde(default)]
+    pub port: u16,
+    // 连接耗时（毫秒）
+    pub latency: f64,
+    // 丢包比例和抖动（毫秒），conn.pings 大于 1 时才有意义
+    #[serde(default)]
+    pub loss: f64,
+    #[serde(default)]
+    pub jitter: f64,
+    // 重试次数
+    #[serde(default)]
+    pub retries: usize,
+    pub colo: Option<String>,
+    #[serde(default)]
+    pub protocol: Option<String>,
+    // protocol 为 http3 时 QUIC 探测的耗时（毫秒），没有探测或没有响应时为空
+    #[serde(default)]
+    pub quic: Option<f64>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct DownloadReportEntry {
+    pub ip: IpAddr,
+    #[serde(default)]
+    pub port: u16,
+    // 下载速度（字节/秒）
+    pub speed: f64,
+    // 多连接测试时的单连接下载速度（字节/秒）
+    #[serde(default)]
+    pub single_speed: Option<f64>,
+    // 按时间采样的下载速度及其统计
+    #[serde(default)]
+    pub series: SpeedSeries,
+    #[serde(default)]
+    pub stability: Option<SpeedStability>,
+    // 下载前和下载时的延迟（毫秒），未启用 latency_probe 时为空
+    #[serde(default)]
+    pub idle_latency: Option<f64>,
+    #[serde(default)]
+    pub loaded_latency: Option<f64>,
+    // 下载的重试次数
+    #[serde(default)]
+    pub retries: usize,
+    // https 地址的 TLS 版本和证书信息
+    #[serde(default)]
+    pub tls: Option<TlsInfo>,
+    // 排序使用的分数
+    #[serde(default)]
+    pub score: Option<f64>,
+    pub colo: Option<String>,
+    #[serde(default)]
+    pub protocol: Option<String>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct UploadReportEntry {
+    pub ip: IpAddr,
+    #[serde(default)]
+    pub port: u16,
+    // 上传速度（字节/秒）
+    pub speed: f64,
+    pub colo: Option<String>,
+    #[serde(default)]
+    pub protocol: Option<String>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+}
+
+// 一次完整测速的结果，用于 HTTP API 等需要 json 输出的地方
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct RunReport {
+    pub summary: RunSummary,
+    pub conn: Vec<ConnReportEntry>,
+    pub download: Vec<DownloadReportEntry>,
+    // 未启用上传测试时为空
+    #[serde(default)]
+    pub upload: Vec<UploadReportEntry>,
+    // 连接失败和下载失败的地址
+    #[serde(default)]
+    pub conn_failed: Vec<SocketAddr>,
+    #[serde(default)]
+    pub download_failed: Vec<SocketAddr>,
+    #[serde(default)]
+    pub upload_failed: Vec<SocketAddr>,
+    // 整次测速耗时（秒）
+    #[serde(default)]
+    pub duration: f64,
+    // ip 来源中包含上次的结果文件时，与上次结果的对比
+    #[serde(default)]
+    pub comparison: Vec<ComparisonEntry>,
+}
+
+// 同一个地址上次和本次的测速数据，延迟为毫秒，速度为字节/秒
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ComparisonEntry {
+    pub ip: IpAddr,
+    pub port: u16,
+    pub old_latency: Option<f64>,
+    pub new_latency: Option<f64>,
+    pub old_speed: Option<f64>,
+    pub new_speed: Option<f64>,
+}
+
+// 结果文件中的一个地址，延迟为毫秒，速度为字节/秒
+#[derive(Clone, Debug, PartialEq)]
+pub struct ResultRow {
+    pub ip: IpAddr,
+    pub port: u16,
+    pub latency: Option<f64>,
+    pub speed: Option<f64>,
+    pub score: Option<f64>,
+    pub colo: Option<String>,
+}
+
+impl ResultRow {
+    pub fn addr(&self) -> SocketAddr {
+        SocketAddr::new(self.ip, self.port)
+    }
+}
+
+const CSV_HEADER: [&str; 10] = [
+    "ip", "port", "latency", "loss", "jitter", "speed", "score", "colo", "protocol", "tags",
+];
+
+fn csv_field(x: &str) -> String {
+    match x.contains([',', '"', '\n']) {
+        true => format!("\"{}\"", x.replace('"', "\"\"")),
+        false => x.to_string(),
+    }
+}
+
+fn opt_text(x: Option<f64>) -> String {
+    x.map(|x| format!("{x:.2}")).unwrap_or_default()
+}
+
+// 按逗号分隔一行，支持引号包围的字段
+fn split_csv_line(line: &str) -> Vec<String> {
+    let mut fields = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                field.push('"');
+                chars.next();
+            }
+            '"' => quoted = !quoted,
+            ',' if !quoted => fields.push(std::mem::take(&mut field)),
+            c => field.push(c),
+        }
+    }
+    fields.push(field);
+    fields
+}
+
+// 解析 to_csv 写出的 csv，按表头查找列，只需要 ip 列，其他列可以缺少或为空
+pub fn parse_csv(content: &str) -> Result<Vec<ResultRow>, Box<dyn Error>> {
+    let mut lines = content.lines().filter(|x| !x.trim().is_empty());
+    let header = split_csv_line(lines.next().ok_or("empty csv")?);
+    let column = |name: &str| header.iter().position(|x| x.trim() == name);
+    let ip_column = column("ip").ok_or("csv 缺少 ip 列")?;
+    let (port, latency, speed, score, colo) = (
+        column("port"),
+        column("latency"),
+        column("speed"),
+        column("score"),
+        column("colo"),
+    );
+
+    let mut rows = Vec::new();
+    for line in lines {
+        let fields = split_csv_line(line);
+        let get = |i: Option<usize>| {
+            i.and_then(|i| fields.get(i))
+                .map(|x| x.trim())
+                .filter(|x| !x.is_empty())
+        };
+        let ip = match get(Some(ip_column)).and_then(|x| x.parse().ok()) {
+            Some(ip) => ip,
+            None => {
+                println!("忽略 csv 中无效的行: {line}");
+                continue;
+            }
+        };
+        rows.push(ResultRow {
+            ip,
+            // 缺少端口时为 0，加载来源后替换为配置的端口
+            port: get(port).and_then(|x| x.parse().ok()).unwrap_or_default(),
+            latency: get(latency).and_then(|x| x.parse().ok()),
+            speed: get(speed).and_then(|x| x.parse().ok()),
+            score: get(score).and_then(|x| x.parse().ok()),
+            colo: get(colo).map(|x| x.to_string()),
+        });
+    }
+    Ok(rows)
+}
+
+// 按文件扩展名解析 json 或 csv 格式的结果文件
+pub fn parse_result(name: &str, content: &str) -> Result<Vec<ResultRow>, Box<dyn Error>> {
+    match name.to_lowercase() {
+        x if x.ends_with(".json") => Ok(serde_json::from_str::<RunReport>(content)?.rows()),
+        x if x.ends_with(".csv") => parse_csv(content),
+        _ => Err(format!("{name} 不是 json 或 csv 文件").into()),
+    }
+}
+
+// 按 path 的扩展名把结果写为 json 或 csv
+pub fn write_result(path: &str, report: &RunReport) -> Result<(), Box<dyn Error>> {
+    let content = match path.to_lowercase() {
+        x if x.ends_with(".csv") => report.to_csv(),
+        _ => serde_json::to_string_pretty(report)?,
+    };
+    fs::write(path, content)?;
+    Ok(())
+}
+
+impl RunReport {
+    pub fn new(
+        conn: &ConnectTestResult,
+        download: &DownloadTestResult,
+        summary: RunSummary,
+    ) -> Self {
+        let conn_entries = conn
+            .list()
+            .iter()
+            .map(|x| ConnReportEntry {
+                ip: x.ip,
+                port: x.port,
+                latency: x.cost.as_secs_f64() * 1000.0,
+                loss: x.loss,
+                jitter: x.jitter.as_secs_f64() * 1000.0,
+                retries: x.retries,
+                colo: x.colo.clone(),
+                protocol: x.protocol.clone(),
+                quic: x.quic.flatten().map(|x| x.as_secs_f64() * 1000.0),
+                tags: x.tags.clone(),
+            })
+            .collect();
+
+        let download_entries = match &download.list {
+            None => Vec::new(),
+            Some(list) => list
+                .iter()
+                .map(|x| DownloadReportEntry {
+                    ip: x.ip,
+                    port: x.port,
+                    speed: x.speed.byte_value(),
+                    single_speed: x.single_speed.as_ref().map(|x| x.byte_value()),
+                    series: x.series.clone(),
+                    stability: x.series.stability(),
+                    idle_latency: x.idle_latency.map(|x| x.as_secs_f64() * 1000.0),
+                    loaded_latency: x.loaded_latency.map(|x| x.as_secs_f64() * 1000.0),
+                    retries: x.retries,
+                    tls: x.tls.clone(),
+                    score: x.score,
+                    colo: x.colo.clone(),
+                    protocol: x.protocol.clone(),
+                    tags: x.tags.clone(),
+                })
+                .collect(),
+        };
+
+        Self {
+            summary,
+            conn: conn_entries,
+            download: download_entries,
+            upload: Vec::new(),
+            conn_failed: conn.failed().to_vec(),
+            download_failed: download.failed.clone(),
+            upload_failed: Vec::new(),
+            duration: 0.0,
+            comparison: Vec::new(),
+        }
+    }
+
+    pub fn set_upload(&mut self, upload: &UploadTestResult) {
+        self.upload = upload
+            .list
+            .iter()
+            .flatten()
+            .map(|x| UploadReportEntry {
+                ip: x.ip,
+                port: x.port,
+                speed: x.speed.byte_value(),
+                colo: x.colo.clone(),
+                protocol: x.protocol.clone(),
+                tags: x.tags.clone(),
+            })
+            .collect();
+        self.upload_failed = upload.failed.clone();
+    }
+
+    // 下载结果中的地址按下载结果的顺序在前，之后是只有连通性测试数据的地址
+    fn entries(&self) -> Vec<(Option<&ConnReportEntry>, Option<&DownloadReportEntry>)> {
+        let conn: HashMap<SocketAddr, &ConnReportEntry> = self
+            .conn
+            .iter()
+            .map(|x| (SocketAddr::new(x.ip, x.port), x))
+            .collect();
+        let mut entries: Vec<_> = self
+            .download
+            .iter()
+            .map(|x| (conn.get(&SocketAddr::new(x.ip, x.port)).copied(), Some(x)))
+            .collect();
+        let downloaded: HashSet<SocketAddr> = self
+            .download
+            .iter()
+            .map(|x| SocketAddr::new(x.ip, x.port))
+            .collect();
+        self.conn
+            .iter()
+            .filter(|x| !downloaded.contains(&SocketAddr::new(x.ip, x.port)))
+            .for_each(|x| entries.push((Some(x), None)));
+        entries
+    }
+
+    pub fn rows(&self) -> Vec<ResultRow> {
+        self.entries()
+            .into_iter()
+            .filter_map(|(conn, download)| {
+                let (ip, port) = match (conn, download) {
+                    (_, Some(x)) => (x.ip, x.port),
+                    (Some(x), None) => (x.ip, x.port),
+                    (None, None) => return None,
+                };
+                Some(ResultRow {
+                    ip,
+                    port,
+                    latency: conn.map(|x| x.latency),
+                    speed: download.map(|x| x.speed),
+                    score: download.and_then(|x| x.score),
+                    colo: download
+                        .and_then(|x| x.colo.clone())
+                        .or_else(|| conn.and_then(|x| x.colo.clone())),
+                })
+            })
+            .collect()
+    }
+
+    // 每个地址一行，速度为字节/秒，延迟和抖动为毫秒，来源标签以分号分隔
+    pub fn to_csv(&self) -> String {
+        let mut content = CSV_HEADER.join(",");
+        content.push('\n');
+        for (conn, download) in self.entries() {
+            let row: Vec<String> = match (conn, download) {
+                (_, Some(x)) => vec![x.ip.to_string(), x.port.to_string()],
+                (Some(x), None) => vec![x.ip.to_string(), x.port.to_string()],
+                (None, None) => continue,
+            }
+            .into_iter()
+            .chain([
+                opt_text(conn.map(|x| x.latency)),
+                opt_text(conn.map(|x| x.loss)),
+                opt_text(conn.map(|x| x.jitter)),
+                opt_text(download.map(|x| x.speed)),
+                opt_text(download.and_then(|x| x.score)),
+                download
+                    .and_then(|x| x.colo.clone())
+                    .or_else(|| conn.and_then(|x| x.colo.clone()))
+                    .unwrap_or_default(),
+                download
+                    .and_then(|x| x.protocol.clone())
+                    .or_else(|| conn.and_then(|x| x.protocol.clone()))
+                    .unwrap_or_default(),
+                download
+                    .map(|x| &x.tags)
+                    .or_else(|| conn.map(|x| &x.tags))
+                    .map(|x| x.join(";"))
+                    .unwrap_or_default(),
+            ])
+            .map(|x| csv_field(&x))
+            .collect();
+            content.push_str(&row.join(","));
+            content.push('\n');
+        }
+        content
+    }
+
+    // 与上次结果中相同地址（ip 和端口）的数据对比，previous 来自 ip 来源中的结果文件
+    // 包括两次结果中的全部地址，上次有而本次失败的地址本次的数据为空
+    pub fn compare(&mut self, previous: &HashMap<SocketAddr, ResultRow>) {
+        let rows = self.rows();
+        let current: HashSet<SocketAddr> = rows.iter().map(|x| x.addr()).collect();
+        let mut missing: Vec<&ResultRow> = previous
+            .iter()
+            .filter(|(addr, _)| !current.contains(addr))
+            .map(|(_, x)| x)
+            .collect();
+        missing.sort_by_key(|x| x.addr());
+
+        self.comparison = rows
+            .iter()
+            .map(|x| (x.addr(), previous.get(&x.addr()), Some(x)))
+            .chain(missing.into_iter().map(|x| (x.addr(), Some(x), None)))
+            .map(|(addr, old, new)| ComparisonEntry {
+                ip: addr.ip(),
+                port: addr.port(),
+                old_latency: old.and_then(|x| x.latency),
+                new_latency: new.and_then(|x| x.latency),
+                old_speed: old.and_then(|x| x.speed),
+                new_speed: new.and_then(|x| x.speed),
+            })
+            .collect();
+    }
+}
@@ -0,0 +1,124 @@
+use std::error::Error;
+use std::future::Future;
+use std::io;
+use std::time::Duration;
+
+// 连接或下载失败的原因，用于判断是否重试
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ErrorKind {
+    // 连接或响应超时
+    Timeout,
+    // 连接被拒绝
+    Refused,
+    // 连接被重置或中断
+    Reset,
+    // 网络或主机不可达
+    Unreachable,
+    Other,
+}
+
+impl ErrorKind {
+    pub fn parse(s: &str) -> Result<Self, Box<dyn Error>> {
+        match s {
+            "timeout" => Ok(Self::Timeout),
+            "refused" => Ok(Self::Refused),
+            "reset" => Ok(Self::Reset),
+            "unreachable" => Ok(Self::Unreachable),
+            "other" => Ok(Self::Other),
+            others => Err(format!("invalid error kind: {others}").into()),
+        }
+    }
+
+    fn from_io(e: &io::Error) -> Option<Self> {
+        match e.kind() {
+            io::ErrorKind::TimedOut => Some(Self::Timeout),
+            io::ErrorKind::ConnectionRefused => Some(Self::Refused),
+            io::ErrorKind::ConnectionReset
+            | io::ErrorKind::ConnectionAborted
+            | io::ErrorKind::BrokenPipe
+            | io::ErrorKind::UnexpectedEof => Some(Self::Reset),
+            io::ErrorKind::HostUnreachable | io::ErrorKind::NetworkUnreachable => {
+                Some(Self::Unreachable)
+            }
+            _ => None,
+        }
+    }
+
+    // 沿着 source 链查找最具体的原因，reqwest 和 hyper 的错误会包装底层的 io 错误
+    pub fn classify(e: &(dyn Error + 'static)) -> Self {
+        let mut cur = Some(e);
+        while let Some(e) = cur {
+            if e.is::<tokio::time::error::Elapsed>() {
+                return Self::Timeout;
+            }
+            if let Some(kind) = e.downcast_ref::<io::Error>().and_then(Self::from_io) {
+                return kind;
+            }
+            if let Some(e) = e.downcast_ref::<reqwest::Error>() {
+                if e.is_timeout() {
+                    return Self::Timeout;
+                }
+            }
+            if let Some(e) = e.downcast_ref::<hyper::Error>() {
+                if e.is_timeout() {
+                    return Self::Timeout;
+                }
+                if e.is_incomplete_message() || e.is_closed() {
+                    return Self::Reset;
+                }
+            }
+            cur = e.source();
+        }
+        Self::Other
+    }
+}
+
+// 失败后的重试策略，attempts 为包括第一次在内的最多尝试次数
+#[derive(Clone, Debug, PartialEq)]
+pub struct RetryPolicy {
+    pub attempts: usize,
+    // 第一次重试前的等待时间，之后每次翻倍
+    pub backoff: Duration,
+    // 可以重试的失败原因
+    pub kinds: Vec<ErrorKind>,
+}
+
+impl Default for RetryPolicy {
+    // 默认不重试
+    fn default() -> Self {
+        Self {
+            attempts: 1,
+            backoff: Duration::ZERO,
+            kinds: Vec::new(),
+        }
+    }
+}
+
+impl RetryPolicy {
+    // 第 retries 次重试前的等待时间
+    pub fn delay(&self, retries: usize) -> Duration {
+        self.backoff * 2u32.saturating_pow(retries.saturating_sub(1) as u32)
+    }
+
+    // 按策略执行 f，返回最后一次的结果和重试次数
+    pub async fn run<T, F, Fut>(&self, mut f: F) -> (Result<T, Box<dyn Error>>, usize)
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T, Box<dyn Error>>>,
+    {
+        let mut retries = 0;
+        loop {
+            match f().await {
+                Ok(x) => return (Ok(x), retries),
+                Err(e) => {
+                    let retryable = self.kinds.contains(&ErrorKind::classify(e.as_ref()));
+                    if retries + 1 >= self.attempts.max(1) || !retryable {
+                        return (Err(e), retries);
+                    }
+                }
+            }
+            retries += 1;
+            tokio::time::sleep(self.delay(retries)).await;
+        }
+    }
+}
@@ -0,0 +1,139 @@
+use super::conn::ConnectTestResult;
+use super::download::{DownloadTestResult, Speed, SpeedFormat};
+
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::fmt::Display;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::net::IpAddr;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// 单次测速的结果摘要，按行以 json 格式追加到历史文件中
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct RunSummary {
+    // unix 时间戳（秒）
+    pub time: u64,
+    pub best_ip: Option<IpAddr>,
+    #[serde(default)]
+    pub best_port: Option<u16>,
+    // 连接耗时（毫秒）
+    pub latency: Option<f64>,
+    // 下载速度（字节/秒）
+    pub speed: Option<f64>,
+    pub colo: Option<String>,
+    // 与上一次测速相比的变化，不写入历史文件
+    #[serde(skip)]
+    pub latency_delta: Option<f64>,
+    #[serde(skip)]
+    pub speed_delta: Option<f64>,
+    // 显示速度使用的格式，不写入历史文件
+    #[serde(skip)]
+    pub format: SpeedFormat,
+}
+
+impl RunSummary {
+    pub fn new(
+        conn: &ConnectTestResult,
+        download: &DownloadTestResult,
+        format: SpeedFormat,
+    ) -> Self {
+        let time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|x| x.as_secs())
+            .unwrap_or_default();
+        let best = download.best();
+        let best_ip = best.map(|x| x.ip);
+        let conn_stats = best.and_then(|x| conn.get(x.addr()));
+
+        Self {
+            time,
+            best_ip,
+            best_port: best.map(|x| x.port),
+            latency: conn_stats.map(|x| x.cost.as_secs_f64() * 1000.0),
+            speed: best.map(|x| x.speed.byte_value()),
+            colo: best
+                .and_then(|x| x.colo.clone())
+                .or_else(|| conn_stats.and_then(|x| x.colo.clone())),
+            latency_delta: None,
+            speed_delta: None,
+            format,
+        }
+    }
+
+    // 计算与 prev 相比的变化
+    pub fn compare(&mut self, prev: &RunSummary) {
+        self.latency_delta = self.latency.zip(prev.latency).map(|(x, y)| x - y);
+        self.speed_delta = self.speed.zip(prev.speed).map(|(x, y)| x - y);
+    }
+
+    pub fn speed_text(&self) -> String {
+        self.fmt_speed(self.speed)
+    }
+
+    pub fn speed_delta_text(&self) -> String {
+        match self.speed_delta {
+            Some(x) if x < 0.0 => format!("-{}", self.fmt_speed(Some(-x))),
+            Some(x) => format!("+{}", self.fmt_speed(Some(x))),
+            None => "-".to_string(),
+        }
+    }
+
+    fn fmt_speed(&self, speed: Option<f64>) -> String {
+        match speed {
+            Some(x) => Speed::from_bytes(x).format(self.format),
+            None => "-".to_string(),
+        }
+    }
+
+    pub fn latency_text(&self) -> String {
+        self.latency
+            .map(|x| format!("{x:.0}ms"))
+            .unwrap_or("-".to_string())
+    }
+
+    pub fn latency_delta_text(&self) -> String {
+        self.latency_delta
+            .map(|x| format!("{x:+.0}ms"))
+            .unwrap_or("-".to_string())
+    }
+}
+
+impl Display for RunSummary {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let best_ip = match self.best_ip {
+            Some(ip) => ip.to_string(),
+            None => return write!(f, "本次测速没有有效的下载数据"),
+        };
+        write!(
+            f,
+            "最快 IP: {best_ip}\n端口: {}\n数据中心: {}\n延迟: {} ({})\n下载速度: {} ({})",
+            self.best_port
+                .map(|x| x.to_string())
+                .as_deref()
+                .unwrap_or("-"),
+            self.colo.as_deref().unwrap_or("-"),
+            self.latency_text(),
+            self.latency_delta_text(),
+            self.speed_text(),
+            self.speed_delta_text(),
+        )
+    }
+}
+
+// 读取历史文件，无法解析的行会被跳过
+pub fn load_history(path: &str) -> Vec<RunSummary> {
+    match fs::read_to_string(path) {
+        Ok(content) => content
+            .lines()
+            .filter_map(|x| serde_json::from_str(x).ok())
+            .collect(),
+        Err(_) => Vec::new(),
+    }
+}
+
+pub fn append_history(path: &str, summary: &RunSummary) -> Result<(), Box<dyn Error>> {
+    let mut f = OpenOptions::new().create(true).append(true).open(path)?;
+    writeln!(f, "{}", serde_json::to_string(summary)?)?;
+    Ok(())
+}
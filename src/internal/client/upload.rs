@@ -0,0 +1,98 @@
+use super::download::{Speed, SpeedFormat};
+use crate::internal::source::IpList;
+use std::fmt::Display;
+use std::net::{IpAddr, SocketAddr};
+
+pub struct UploadTestStats {
+    pub ip: IpAddr,
+    pub port: u16,
+    pub speed: Speed,
+    // Cloudflare 数据中心，从 cf-ray 响应头中获取
+    pub colo: Option<String>,
+    // 实际使用的协议，如 HTTP/1.1、HTTP/2.0
+    pub protocol: Option<String>,
+    // ip 所属的来源标签
+    pub tags: Vec<String>,
+    // 显示速度使用的格式
+    pub format: SpeedFormat,
+}
+
+impl Display for UploadTestStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{:<21} 上传速度 {}",
+            self.addr().to_string(),
+            self.speed.format(self.format)
+        )?;
+        if let Some(colo) = &self.colo {
+            write!(f, " 数据中心 {colo}")?;
+        }
+        if let Some(protocol) = &self.protocol {
+            write!(f, " 协议 {protocol}")?;
+        }
+        if !self.tags.is_empty() {
+            write!(f, " 来源 {}", self.tags.join(","))?;
+        }
+        Ok(())
+    }
+}
+
+impl UploadTestStats {
+    pub fn new(addr: SocketAddr, speed: Speed) -> Self {
+        Self {
+            ip: addr.ip(),
+            port: addr.port(),
+            speed,
+            colo: None,
+            protocol: None,
+            tags: Vec::new(),
+            format: SpeedFormat::default(),
+        }
+    }
+
+    pub fn addr(&self) -> SocketAddr {
+        SocketAddr::new(self.ip, self.port)
+    }
+}
+
+pub trait UploadTest {
+    fn upload_test(&self) -> UploadTestResult;
+}
+
+pub struct UploadTestResult {
+    pub top: usize,
+    pub list: Option<Vec<UploadTestStats>>,
+    // 上传失败的地址
+    pub failed: Vec<SocketAddr>,
+}
+
+impl UploadTestResult {
+    pub fn apply_tags(&mut self, list: &IpList) {
+        if let Some(stats) = &mut self.list {
+            stats.iter_mut().for_each(|x| x.tags = list.tags_of(&x.ip));
+        }
+    }
+}
+
+impl Display for UploadTestResult {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.list {
+            None => write!(
+                f,
+                "没有上传数据，可以尝试以下方法后再次重试：\n1. 检查上传地址是否接受 POST 请求\n2. 减小上传配置中 size 数值\n3. 增大上传配置中 timeout 数值"
+            ),
+            Some(list) => {
+                let mut content = String::new();
+                content.push_str("上传测速结果：\n");
+                content.push_str(format!("总有效测速数据 {} 条\n", list.len()).as_str());
+                content.push_str(format!("下面是上传速度最快的 {} 条数据：\n", self.top).as_str());
+                list.iter().take(self.top).for_each(|x| {
+                    content.push_str(format!("{}\n", x).as_str());
+                });
+
+                write!(f, "{}", content)
+            }
+        }
+    }
+}
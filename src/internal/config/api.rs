@@ -1,16 +1,41 @@
 use crate::internal::client::conn::ConnTest;
-use crate::internal::client::download::DownloadTest;
-use crate::internal::network::http::HttpClient;
+use crate::internal::client::download::{DownloadTest, SpeedFormat, SpeedPrefix, SpeedUnit};
+use crate::internal::client::retry::{ErrorKind, RetryPolicy};
+use crate::internal::client::upload::UploadTest;
+use crate::internal::network::http::{HttpClient, HttpOptions, Protocol, Scheme, UploadClient};
+use crate::internal::network::quic::QuicClient;
 use crate::internal::network::tcp::TcpClient;
+use crate::internal::network::tls::{self, TlsOptions, TlsVersion};
+use crate::internal::source::{self, IpList, Source};
+
+use crate::internal::action::dns;
+use crate::internal::action::export::Format;
+use crate::internal::rank;
 
 use super::super::client::args;
 use super::def::Config;
 
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue, COOKIE};
+use reqwest::Url;
+use std::error::Error;
 use std::fs;
-use std::io::{BufRead, BufReader};
 use std::net::{IpAddr, SocketAddr};
+use std::path::Path;
+use std::process;
 use std::time::Duration;
 
+// config init 生成的默认配置
+const DEFAULT_CONFIG: &str = include_str!("../../config/example.yaml");
+
+fn check_choice(errors: &mut Vec<String>, name: &str, value: &str, choices: &[&str]) {
+    if !choices.contains(&value) {
+        errors.push(format!(
+            "{name}: 无效的值 {value}，可选 {}",
+            choices.join(", ")
+        ));
+    }
+}
+
 impl Config {
     fn check(mut self) -> Self {
         if self.conn.timeout > 60 {
@@ -25,70 +50,435 @@ impl Config {
             self.download.timeout = args::DEFAULT_DOWNLOAD_TIMEOUT;
         };
 
+        if self.upload.timeout > 60 {
+            self.upload.timeout = args::DEFAULT_DOWNLOAD_TIMEOUT;
+        };
+
         self
     }
 
     // 读配置
     fn new(path: &str) -> Self {
         println!("从 {path} 加载配置 ...");
-        let file_content: String = fs::read_to_string(path).unwrap();
-        let conf: Config = serde_yaml::from_str(&file_content).unwrap();
-        conf.check()
+        Self::load(path).unwrap()
+    }
+
+    pub fn load(path: &str) -> Result<Self, Box<dyn Error>> {
+        let file_content: String = fs::read_to_string(path)?;
+        let conf: Config = serde_yaml::from_str(&file_content)?;
+        Ok(conf.check())
+    }
+
+    // 把带注释的默认配置写入 path，force 为 false 时不覆盖已存在的文件
+    pub fn write_default(path: &str, force: bool) -> Result<(), Box<dyn Error>> {
+        if !force && Path::new(path).exists() {
+            return Err(format!("{path} 已存在，使用 --force 覆盖").into());
+        }
+        fs::write(path, DEFAULT_CONFIG)?;
+        Ok(())
     }
 
-    pub fn init(conf_path: &str, ip_path: &str) -> (Self, Vec<IpAddr>) {
+    // 检查只有在使用时才会解析的选项，返回所有错误
+    pub fn validate(&self) -> Vec<String> {
+        let mut errors = Vec::new();
+        if let Err(e) = Url::parse(&self.url) {
+            errors.push(format!("url: {e}"));
+        }
+        check_choice(
+            &mut errors,
+            "scheme",
+            &self.scheme,
+            &["auto", "http", "https"],
+        );
+        check_choice(
+            &mut errors,
+            "protocol",
+            &self.protocol,
+            &["auto", "http1", "http2", "http3"],
+        );
+        check_choice(
+            &mut errors,
+            "speed.unit",
+            &self.speed.unit,
+            &["bit", "byte"],
+        );
+        check_choice(
+            &mut errors,
+            "speed.prefix",
+            &self.speed.prefix,
+            &["si", "iec"],
+        );
+        for x in &self.phases {
+            check_choice(&mut errors, "phases", x, &["conn", "download", "upload"]);
+        }
+        let has_phase = |x: &str| self.phases.iter().any(|y| y == x);
+        if (self.upload.enable || has_phase("upload")) && !has_phase("download") {
+            errors.push("phases: 上传测试使用下载测试的结果，需要同时包含 download".to_string());
+        }
+        let transfers = self.upload.enable || has_phase("download") || has_phase("upload");
+        if self.protocol == "http3" && transfers {
+            errors.push("protocol: 下载和上传测试不支持 http3，phases 只能为 [conn]".to_string());
+        }
+        check_choice(
+            &mut errors,
+            "conn.method",
+            &self.conn.method,
+            &["http", "tcp", "quic"],
+        );
+        for x in &self.sources {
+            check_choice(
+                &mut errors,
+                "sources.by",
+                &x.by,
+                &["", "speed", "score", "latency"],
+            );
+        }
+        if let Err(e) = self.tls_options() {
+            errors.push(format!("tls: {e}"));
+        }
+        if self.tls.inspect && !cfg!(feature = "tls-inspect") {
+            errors.push("tls.inspect: 编译时没有启用 tls-inspect 功能".to_string());
+        }
+        if let Err(e) = self.request_headers() {
+            errors.push(format!("request: {e}"));
+        }
+        if !self.request.sni.is_empty()
+            && !matches!(
+                url::Host::parse(&self.request.sni),
+                Ok(url::Host::Domain(_))
+            )
+        {
+            errors.push(format!("request.sni: {} 不是有效的域名", self.request.sni));
+        }
+        if self.protocol == "http2" && !self.request.host.is_empty() {
+            // HTTP/2 的 :authority 总是 SNI，无法使用不同的 Host
+            let rewritten = [&self.url, &self.upload.url]
+                .into_iter()
+                .filter_map(|x| Url::parse(x).ok())
+                .any(|x| {
+                    let sni = match self.request.sni.is_empty() {
+                        true => x.host_str(),
+                        false => Some(self.request.sni.as_str()),
+                    };
+                    sni != Some(self.request.host.as_str())
+                });
+            if rewritten {
+                errors.push(
+                    "request.host: protocol 为 http2 时 host 必须与 sni 或测试地址中的域名相同"
+                        .to_string(),
+                );
+            }
+        }
+        for x in &self.retry.on {
+            if let Err(e) = ErrorKind::parse(x) {
+                errors.push(format!("retry.on: {e}"));
+            }
+        }
+        if let Err(e) = rank::create_strategy(&self.rank) {
+            errors.push(format!("rank: {e}"));
+        }
+        if self.dns.enable {
+            if let Err(e) = dns::create_provider(self) {
+                errors.push(format!("dns: {e}"));
+            }
+        }
+        if self.export.enable {
+            if let Err(e) = Format::parse(&self.export.format) {
+                errors.push(format!("export.format: {e}"));
+            }
+        }
+        if let Err(e) = self.server.listen.parse::<SocketAddr>() {
+            errors.push(format!("server.listen: {e}"));
+        }
+        errors
+    }
+
+    // sources 为命令行中指定的 ip 来源，phases 为命令行中指定的阶段
+    pub fn init(
+        conf_path: &str,
+        mut sources: Vec<Source>,
+        phases: Option<Vec<String>>,
+    ) -> (Self, IpList) {
         // 加载配置
-        let conf = Config::new(conf_path);
-        // 读取 ip 文件
-        let ips = load_ips(ip_path);
+        let mut conf = Config::new(conf_path);
+        // 命令行指定的阶段优先，不再因为 upload.enable 追加上传测试
+        if let Some(phases) = phases {
+            conf.phases = phases;
+            conf.upload.enable = false;
+        }
+        // 在加载 ip 来源和测速之前检查配置，避免测速结束后才因为配置错误退出
+        let errors = conf.validate();
+        if !errors.is_empty() {
+            println!("配置文件 {conf_path} 有 {} 个错误：", errors.len());
+            errors.iter().for_each(|x| println!("  {x}"));
+            process::exit(1);
+        }
+        // 合并命令行和配置文件中的 ip 来源
+        sources.extend(conf.sources.iter().cloned());
+        if sources.is_empty() {
+            sources.push(Source::new(args::DEFAULT_IP_FILE));
+        }
+        // 执行连通性测试的两阶段扫描时，第一阶段每块只取 probes 个地址
+        let mut cidr = conf.cidr.clone();
+        if conf.conn.adaptive.enable && conf.phases.iter().any(|x| x == "conn") {
+            cidr.per_block = conf.conn.adaptive.probes.max(1);
+        }
+        let mut ips = source::load(&sources, &conf.fetch, &cidr);
+        conf.apply_exclude(&mut ips);
+        // 结果文件中没有端口的地址按配置的端口对比
+        ips.set_default_port(conf.ports()[0]);
 
         (conf, ips)
     }
 
-    pub fn create_conn_test_client(&self, ips: Vec<IpAddr>) -> Box<dyn ConnTest> {
+    // 需要测试的端口，ports 为空时使用 port
+    pub fn ports(&self) -> Vec<u16> {
+        if self.ports.is_empty() {
+            return vec![self.port];
+        }
+        self.ports.clone()
+    }
+
+    // 排除 exclude 和 exclude_file 中的 ip 与网段
+    pub fn apply_exclude(&self, ips: &mut IpList) {
+        let mut nets = Vec::new();
+        for x in &self.exclude {
+            match source::parse_net(x) {
+                Some(net) => nets.push(net),
+                None => println!("忽略 exclude 中无效的 ip 或网段: {x}"),
+            }
+        }
+        if !self.exclude_file.is_empty() {
+            match fs::read_to_string(&self.exclude_file) {
+                Ok(content) => nets.extend(source::parse_nets(&content, &self.exclude_file)),
+                Err(e) => println!("读取排除列表 {} 失败: {e}", self.exclude_file),
+            }
+        }
+        if nets.is_empty() {
+            return;
+        }
+
+        let excluded = ips.exclude(&nets);
+        println!("已排除 {excluded} 个 ip，剩余 {} 个 ip", ips.ips.len());
+    }
+
+    // 每个 ip 的每个端口
+    pub fn socket_addrs(&self, ips: Vec<IpAddr>) -> Vec<SocketAddr> {
         let mut socket_addrs = Vec::new();
-        let timeout = Duration::from_secs(self.conn.timeout);
         for ip in ips {
-            socket_addrs.push(SocketAddr::new(ip, self.port));
+            for port in self.ports() {
+                socket_addrs.push(SocketAddr::new(ip, port));
+            }
         }
+        socket_addrs
+    }
+
+    pub fn create_conn_test_client(&self, ips: Vec<IpAddr>) -> Box<dyn ConnTest> {
+        let socket_addrs = self.socket_addrs(ips);
+        let timeout = Duration::from_secs(self.conn.timeout);
 
         match self.conn.method.as_str() {
             "http" => {
-                return Box::new(HttpClient::build(
+                let mut options = self.http_options();
+                options.pings = self.conn.pings;
+                Box::new(HttpClient::build(
                     self.url.as_str().parse().unwrap(),
                     socket_addrs,
                     timeout,
                     self.conn.top,
-                ));
+                    options,
+                ))
             }
-            "tcp" => return Box::new(TcpClient::build(socket_addrs, timeout, self.conn.top)),
+            "tcp" => Box::new(TcpClient::build(
+                socket_addrs,
+                timeout,
+                self.conn.top,
+                self.conn.pings,
+                self.retry_policy(),
+            )),
+            "quic" => self.create_quic_test_client(socket_addrs),
 
             others => panic!("invalid method: {others}"),
         }
     }
 
-    pub fn create_download_test_client(&self, ips: Vec<IpAddr>) -> Box<dyn DownloadTest> {
-        let mut socket_addrs = Vec::new();
+    // 通过 QUIC 版本协商测试连通性，protocol 为 http3 时在连通性测试之后对成功的地址再测一次
+    pub fn create_quic_test_client(&self, socket_addrs: Vec<SocketAddr>) -> Box<dyn ConnTest> {
+        Box::new(QuicClient::build(
+            socket_addrs,
+            Duration::from_secs(self.conn.timeout),
+            self.conn.top,
+            self.conn.pings,
+            self.retry_policy(),
+        ))
+    }
+
+    pub fn create_download_test_client(
+        &self,
+        socket_addrs: Vec<SocketAddr>,
+    ) -> Box<dyn DownloadTest> {
         let timeout = Duration::from_secs(self.conn.timeout);
-        ips.into_iter()
-            .for_each(|x| socket_addrs.push(SocketAddr::new(x, self.port)));
-        return Box::new(HttpClient::build(
+        let mut options = self.http_options();
+        if options.protocol == Protocol::Http3 {
+            panic!("invalid protocol: 下载测试不支持 http3");
+        }
+        options.streams = self.download.streams;
+        options.sample_interval = Duration::from_millis(self.download.sample_interval);
+        options.warmup = Duration::from_millis(self.download.warmup);
+        options.latency_probe = Duration::from_millis(self.download.latency_probe);
+        options.probe_timeout = Duration::from_secs(self.conn.timeout);
+        Box::new(HttpClient::build(
             self.url.as_str().parse().unwrap(),
             socket_addrs,
             timeout,
             self.download.top,
-        ));
+            options,
+        ))
+    }
+
+    pub fn create_upload_test_client(&self, socket_addrs: Vec<SocketAddr>) -> Box<dyn UploadTest> {
+        let url = match self.upload.url.is_empty() {
+            true => &self.url,
+            false => &self.upload.url,
+        };
+        let options = self.http_options();
+        if options.protocol == Protocol::Http3 {
+            panic!("invalid protocol: 上传测试不支持 http3");
+        }
+        let client = HttpClient::build(
+            url.as_str().parse().unwrap(),
+            socket_addrs,
+            Duration::from_secs(self.upload.timeout),
+            self.upload.top,
+            options,
+        );
+        Box::new(UploadClient::build(client, self.upload.size))
+    }
+
+    pub fn speed_format(&self) -> SpeedFormat {
+        let unit = match self.speed.unit.as_str() {
+            "bit" => SpeedUnit::Bit,
+            "byte" => SpeedUnit::Byte,
+            others => panic!("invalid speed unit: {others}"),
+        };
+        let prefix = match self.speed.prefix.as_str() {
+            "si" => SpeedPrefix::Si,
+            "iec" => SpeedPrefix::Iec,
+            others => panic!("invalid speed prefix: {others}"),
+        };
+        SpeedFormat { unit, prefix }
+    }
+
+    pub fn http_options(&self) -> HttpOptions {
+        let scheme = match self.scheme.as_str() {
+            "auto" => Scheme::Auto,
+            "http" => Scheme::Http,
+            "https" => Scheme::Https,
+            others => panic!("invalid scheme: {others}"),
+        };
+        let protocol = match self.protocol.as_str() {
+            "auto" => Protocol::Auto,
+            "http1" => Protocol::Http1,
+            "http2" => Protocol::Http2,
+            "http3" => Protocol::Http3,
+            others => panic!("invalid protocol: {others}"),
+        };
+        let not_empty = |x: &String| match x.is_empty() {
+            true => None,
+            false => Some(x.clone()),
+        };
+        HttpOptions {
+            scheme,
+            protocol,
+            retry: self.retry_policy(),
+            user_agent: self.request.user_agent.clone(),
+            headers: self.request_headers().unwrap(),
+            sni: not_empty(&self.request.sni),
+            host: not_empty(&self.request.host),
+            tls: self
+                .tls_options()
+                .unwrap_or_else(|e| panic!("invalid tls: {e}")),
+            speed_format: self.speed_format(),
+            ..Default::default()
+        }
     }
-}
 
-// 读 ip 文件
-fn load_ips(ip_file_path: &str) -> Vec<IpAddr> {
-    println!("从 {ip_file_path} 加载 ip 数据 ...");
-    let f = fs::File::open(ip_file_path).unwrap();
-    let lines = BufReader::new(f).lines();
-    let mut ips = Vec::new();
-    for line in lines {
-        ips.push(line.unwrap().parse().unwrap());
+    // 读取 tls 中的证书文件
+    pub fn tls_options(&self) -> Result<TlsOptions, Box<dyn Error>> {
+        let tls = &self.tls;
+        let ca_pem = match tls.ca_file.is_empty() {
+            true => None,
+            false => Some(fs::read(&tls.ca_file).map_err(|e| format!("{}: {e}", tls.ca_file))?),
+        };
+        let ca = match &ca_pem {
+            Some(pem) => tls::parse_certificates(pem)?,
+            None => Vec::new(),
+        };
+        let identity_pem = match (tls.client_cert.is_empty(), tls.client_key.is_empty()) {
+            (true, true) => None,
+            (false, false) => {
+                let cert =
+                    fs::read(&tls.client_cert).map_err(|e| format!("{}: {e}", tls.client_cert))?;
+                let key =
+                    fs::read(&tls.client_key).map_err(|e| format!("{}: {e}", tls.client_key))?;
+                Some((cert, key))
+            }
+            _ => return Err("client_cert 和 client_key 需要同时设置".into()),
+        };
+        let identity = match &identity_pem {
+            Some((cert, key)) => Some(reqwest::Identity::from_pkcs8_pem(cert, key)?),
+            None => None,
+        };
+        let min_version = match tls.min_version.as_str() {
+            "" => None,
+            x => Some(TlsVersion::parse(x)?),
+        };
+        Ok(TlsOptions {
+            verify: tls.verify,
+            ca,
+            #[cfg(feature = "tls-inspect")]
+            ca_pem,
+            identity,
+            #[cfg(feature = "tls-inspect")]
+            identity_pem,
+            min_version,
+            inspect: tls.inspect,
+        })
+    }
+
+    // request 中附加的请求头，cookies 合并为一个 Cookie 请求头
+    pub fn request_headers(&self) -> Result<HeaderMap, Box<dyn Error>> {
+        let mut headers = HeaderMap::new();
+        for (name, value) in &self.request.headers {
+            headers.insert(
+                HeaderName::from_bytes(name.as_bytes())?,
+                HeaderValue::from_str(value)?,
+            );
+        }
+        if !self.request.cookies.is_empty() {
+            let cookies: Vec<String> = self
+                .request
+                .cookies
+                .iter()
+                .map(|(name, value)| format!("{name}={value}"))
+                .collect();
+            headers.insert(COOKIE, HeaderValue::from_str(&cookies.join("; "))?);
+        }
+        Ok(headers)
+    }
+
+    pub fn retry_policy(&self) -> RetryPolicy {
+        let mut kinds = Vec::new();
+        for x in &self.retry.on {
+            match ErrorKind::parse(x) {
+                Ok(kind) => kinds.push(kind),
+                Err(e) => println!("忽略无效的重试条件: {e}"),
+            }
+        }
+        RetryPolicy {
+            attempts: self.retry.attempts.max(1),
+            backoff: Duration::from_millis(self.retry.backoff),
+            kinds,
+        }
     }
-    ips
 }
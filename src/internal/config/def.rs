@@ -2,27 +2,241 @@ extern crate serde;
 extern crate serde_yaml;
 
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+use crate::internal::network::http::DEFAULT_USER_AGENT;
+use crate::internal::source::Source;
 
 #[derive(Debug, PartialEq, Serialize, Deserialize)]
 // 配置说明在 src/config/example.yaml 中
 pub struct Config {
     // 测试 URL
     pub url: String,
+    // 测试时使用的协议：auto, http, https
+    #[serde(default = "default_scheme")]
+    pub scheme: String,
+    // http 版本：auto, http1, http2, http3
+    #[serde(default = "default_protocol")]
+    pub protocol: String,
+    // 连通性测试和下载测试的请求设置
+    #[serde(default)]
+    pub request: RequestConfig,
+    // https 请求的 TLS 设置
+    #[serde(default)]
+    pub tls: TlsConfig,
+    // 速度的显示单位
+    #[serde(default)]
+    pub speed: SpeedConfig,
     // 测试代理端口
+    #[serde(default = "default_port")]
     pub port: u16,
+    // 测试代理端口列表，不为空时代替 port，每个 ip 的每个端口都会被测试
+    #[serde(default)]
+    pub ports: Vec<u16>,
+    // ip 来源，与命令行 -s 指定的来源合并
+    #[serde(default)]
+    pub sources: Vec<Source>,
+    // 远程 ip 来源的下载和缓存
+    #[serde(default)]
+    pub fetch: FetchConfig,
+    // 网段的展开方式
+    #[serde(default)]
+    pub cidr: CidrConfig,
+    // 排除的 ip 或网段
+    #[serde(default)]
+    pub exclude: Vec<String>,
+    // 排除列表文件，每行一个 ip 或网段
+    #[serde(default)]
+    pub exclude_file: String,
+    // 测速流程的阶段：conn, download, upload
+    #[serde(default = "default_phases")]
+    pub phases: Vec<String>,
     // 连通性测试配置
     pub conn: ConnConfig,
     // 下载测试配置
     pub download: DownloadConfig,
+    // 上传测试配置
+    #[serde(default)]
+    pub upload: UploadConfig,
+    // 连接和下载失败时的重试策略
+    #[serde(default)]
+    pub retry: RetryConfig,
+    // 最终结果的排序方式
+    #[serde(default)]
+    pub rank: RankConfig,
+    // hosts 文件更新配置
+    #[serde(default)]
+    pub hosts: HostsConfig,
+    // DNS 记录更新配置
+    #[serde(default)]
+    pub dns: DnsConfig,
+    // 代理客户端配置导出
+    #[serde(default)]
+    pub export: ExportConfig,
+    // 测速历史记录
+    #[serde(default)]
+    pub history: HistoryConfig,
+    // 测速结果文件
+    #[serde(default)]
+    pub result: ResultConfig,
+    // 测速完成后的通知
+    #[serde(default)]
+    pub notify: NotifyConfig,
+    // 守护模式下的 HTTP API
+    #[serde(default)]
+    pub server: ServerConfig,
+}
+
+fn default_port() -> u16 {
+    443
+}
+
+fn default_scheme() -> String {
+    "auto".to_string()
+}
+
+fn default_phases() -> Vec<String> {
+    vec!["conn".to_string(), "download".to_string()]
+}
+
+fn default_protocol() -> String {
+    "auto".to_string()
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SpeedConfig {
+    // bit, byte
+    pub unit: String,
+    // si, iec
+    pub prefix: String,
+}
+
+impl Default for SpeedConfig {
+    fn default() -> Self {
+        Self {
+            unit: "byte".to_string(),
+            prefix: "iec".to_string(),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RequestConfig {
+    pub user_agent: String,
+    // 附加的请求头
+    pub headers: BTreeMap<String, String>,
+    // 附加的 Cookie
+    pub cookies: BTreeMap<String, String>,
+    // TLS 握手使用的 SNI，为空时使用测试 URL 中的主机
+    pub sni: String,
+    // 代替 Host 请求头，为空时使用测试 URL 中的主机
+    pub host: String,
+}
+
+impl Default for RequestConfig {
+    fn default() -> Self {
+        Self {
+            user_agent: DEFAULT_USER_AGENT.to_string(),
+            headers: BTreeMap::new(),
+            cookies: BTreeMap::new(),
+            sni: String::new(),
+            host: String::new(),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TlsConfig {
+    // 是否验证服务器证书
+    pub verify: bool,
+    // 额外信任的 CA 证书文件（PEM，可以包含多个证书）
+    pub ca_file: String,
+    // 客户端证书和私钥文件（PEM，私钥为 PKCS#8 格式），需要同时设置
+    pub client_cert: String,
+    pub client_key: String,
+    // 最低 TLS 版本：1.0, 1.1, 1.2，为空时使用默认值
+    pub min_version: String,
+    // 下载测试后是否单独握手一次，记录协商的 TLS 版本和证书信息，默认不记录
+    pub inspect: bool,
+}
+
+impl Default for TlsConfig {
+    fn default() -> Self {
+        Self {
+            verify: true,
+            ca_file: String::new(),
+            client_cert: String::new(),
+            client_key: String::new(),
+            min_version: String::new(),
+            inspect: false,
+        }
+    }
 }
 
 #[derive(Debug, PartialEq, Serialize, Deserialize)]
 pub struct ConnConfig {
-    // http, tcp
+    // http, tcp, quic
     pub method: String,
     pub timeout: u64,
     pub http: ConnHttpConfig,
     pub top: usize,
+    // 每个地址的测试次数，多于 1 次时计算丢包和抖动
+    #[serde(default = "default_pings")]
+    pub pings: usize,
+    // 大网段的两阶段扫描
+    #[serde(default)]
+    pub adaptive: AdaptiveConfig,
+}
+
+fn default_pings() -> usize {
+    1
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RetryConfig {
+    // 包括第一次在内的最多尝试次数，为 1 时不重试
+    pub attempts: usize,
+    // 第一次重试前的等待时间（毫秒），之后每次翻倍
+    pub backoff: u64,
+    // 可以重试的失败原因：timeout, refused, reset, unreachable, other
+    pub on: Vec<String>,
+}
+
+impl Default for RetryConfig {
+    fn default() -> Self {
+        Self {
+            attempts: 1,
+            backoff: 200,
+            on: vec!["timeout".to_string(), "reset".to_string()],
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AdaptiveConfig {
+    pub enable: bool,
+    // 第一阶段每块（IPv4 /24、IPv6 /48）测试的地址数量，代替 cidr.per_block
+    pub probes: usize,
+    // 第二阶段密集扫描的块数
+    pub blocks: usize,
+    // 第二阶段每块测试的地址数量，0 表示全部地址（IPv6 最多 256 个）
+    pub dense: usize,
+}
+
+impl Default for AdaptiveConfig {
+    fn default() -> Self {
+        Self {
+            enable: false,
+            probes: 2,
+            blocks: 8,
+            dense: 0,
+        }
+    }
 }
 
 #[derive(Debug, PartialEq, Serialize, Deserialize)]
@@ -34,4 +248,359 @@ pub struct ConnHttpConfig {
 pub struct DownloadConfig {
     pub timeout: u64,
     pub top: usize,
+    // 每个 IP 同时下载的连接数，大于 1 时同时显示单连接速度
+    #[serde(default = "default_streams")]
+    pub streams: usize,
+    // 下载速度的采样间隔（毫秒），为 0 时不采样
+    #[serde(default = "default_sample_interval")]
+    pub sample_interval: u64,
+    // 下载开始后不计入平均速度的预热时间（毫秒）
+    #[serde(default = "default_warmup")]
+    pub warmup: u64,
+    // 下载时测试延迟的间隔（毫秒），为 0 时不测试
+    #[serde(default)]
+    pub latency_probe: u64,
+}
+
+fn default_streams() -> usize {
+    1
+}
+
+fn default_sample_interval() -> u64 {
+    250
+}
+
+fn default_warmup() -> u64 {
+    1000
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct UploadConfig {
+    pub enable: bool,
+    // 上传地址，需要接受 POST 请求，为空时使用测试 URL
+    pub url: String,
+    // 每个 IP 上传的字节数
+    pub size: usize,
+    pub timeout: u64,
+    pub top: usize,
+}
+
+impl Default for UploadConfig {
+    fn default() -> Self {
+        Self {
+            enable: false,
+            url: String::new(),
+            size: 10 << 20,
+            timeout: 10,
+            top: 10,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RankConfig {
+    // latency, speed, weighted, lexicographic
+    pub strategy: String,
+    // strategy 为 weighted 时各项指标的权重
+    pub weights: RankWeights,
+}
+
+impl Default for RankConfig {
+    fn default() -> Self {
+        Self {
+            strategy: "speed".to_string(),
+            weights: RankWeights::default(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RankWeights {
+    pub latency: f64,
+    pub loss: f64,
+    pub jitter: f64,
+    pub speed: f64,
+}
+
+impl Default for RankWeights {
+    fn default() -> Self {
+        Self {
+            latency: 1.0,
+            loss: 1.0,
+            jitter: 0.5,
+            speed: 2.0,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct HostsConfig {
+    pub enable: bool,
+    pub path: String,
+    // 额外的域名，测试 URL 中的域名总是会被写入
+    pub names: Vec<String>,
+    pub backup: bool,
+    pub dry_run: bool,
+}
+
+impl Default for HostsConfig {
+    fn default() -> Self {
+        Self {
+            enable: false,
+            path: "/etc/hosts".to_string(),
+            names: Vec::new(),
+            backup: true,
+            dry_run: false,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DnsConfig {
+    pub enable: bool,
+    // cloudflare, rfc2136
+    pub provider: String,
+    // 写入每条记录的 IP 数量
+    pub top: usize,
+    pub ttl: u32,
+    pub records: Vec<String>,
+    pub cloudflare: DnsCloudflareConfig,
+    pub rfc2136: DnsRfc2136Config,
+}
+
+impl Default for DnsConfig {
+    fn default() -> Self {
+        Self {
+            enable: false,
+            provider: "cloudflare".to_string(),
+            top: 1,
+            ttl: 60,
+            records: Vec::new(),
+            cloudflare: DnsCloudflareConfig::default(),
+            rfc2136: DnsRfc2136Config::default(),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DnsCloudflareConfig {
+    pub base_url: String,
+    pub token: String,
+    pub zone_id: String,
+    pub timeout: u64,
+}
+
+impl Default for DnsCloudflareConfig {
+    fn default() -> Self {
+        Self {
+            base_url: "https://api.cloudflare.com/client/v4".to_string(),
+            token: String::new(),
+            zone_id: String::new(),
+            timeout: 10,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DnsRfc2136Config {
+    pub server: String,
+    pub zone: String,
+    pub timeout: u64,
+}
+
+impl Default for DnsRfc2136Config {
+    fn default() -> Self {
+        Self {
+            server: "127.0.0.1:53".to_string(),
+            zone: String::new(),
+            timeout: 10,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ExportConfig {
+    pub enable: bool,
+    // clash, sing-box, xray
+    pub format: String,
+    pub template: String,
+    pub output: String,
+    // 模板中占位节点的名称
+    pub placeholder: String,
+    // 需要加入新节点的分组，为空时不修改分组
+    pub group: String,
+    pub top: usize,
+}
+
+impl Default for ExportConfig {
+    fn default() -> Self {
+        Self {
+            enable: false,
+            format: "clash".to_string(),
+            template: "./template.yaml".to_string(),
+            output: "./proxy.yaml".to_string(),
+            placeholder: "cf-proxy-test".to_string(),
+            group: String::new(),
+            top: 5,
+        }
+    }
+}
+
+#[derive(Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct HistoryConfig {
+    // 为空时不记录历史
+    pub path: String,
+}
+
+#[derive(Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ResultConfig {
+    // 为空时不写入，扩展名为 .csv 时写为 csv，否则写为 json
+    pub path: String,
+}
+
+#[derive(Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct NotifyConfig {
+    pub webhook: WebhookConfig,
+    pub telegram: TelegramConfig,
+    pub smtp: SmtpConfig,
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct WebhookConfig {
+    pub enable: bool,
+    pub url: String,
+    pub method: String,
+    pub template: String,
+    pub timeout: u64,
+    pub retries: usize,
+}
+
+impl Default for WebhookConfig {
+    fn default() -> Self {
+        Self {
+            enable: false,
+            url: String::new(),
+            method: "POST".to_string(),
+            template: r#"{"best_ip":"{{best_ip}}","colo":"{{colo}}","latency":"{{latency}}","latency_delta":"{{latency_delta}}","speed":"{{speed}}","speed_delta":"{{speed_delta}}"}"#.to_string(),
+            timeout: 10,
+            retries: 2,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TelegramConfig {
+    pub enable: bool,
+    pub base_url: String,
+    pub token: String,
+    pub chat_id: String,
+    pub timeout: u64,
+    pub retries: usize,
+}
+
+impl Default for TelegramConfig {
+    fn default() -> Self {
+        Self {
+            enable: false,
+            base_url: "https://api.telegram.org".to_string(),
+            token: String::new(),
+            chat_id: String::new(),
+            timeout: 10,
+            retries: 2,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SmtpConfig {
+    pub enable: bool,
+    pub server: String,
+    pub username: String,
+    pub password: String,
+    pub from: String,
+    pub to: Vec<String>,
+    pub subject: String,
+    pub timeout: u64,
+    pub retries: usize,
+}
+
+impl Default for SmtpConfig {
+    fn default() -> Self {
+        Self {
+            enable: false,
+            server: "127.0.0.1:25".to_string(),
+            username: String::new(),
+            password: String::new(),
+            from: String::new(),
+            to: Vec::new(),
+            subject: "cf-proxy-test 测速结果".to_string(),
+            timeout: 10,
+            retries: 2,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ServerConfig {
+    pub listen: String,
+    // 定时测速的间隔（分钟），为 0 时只在请求 /run 时测速
+    pub interval: u64,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            listen: "127.0.0.1:8080".to_string(),
+            interval: 60,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct FetchConfig {
+    pub cache_dir: String,
+    pub timeout: u64,
+}
+
+impl Default for FetchConfig {
+    fn default() -> Self {
+        Self {
+            cache_dir: "./cache".to_string(),
+            timeout: 10,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CidrConfig {
+    // 每块取的地址数量，0 表示取全部地址
+    pub per_block: usize,
+    // 每个网段最多取的块数
+    pub max_blocks: usize,
+}
+
+impl Default for CidrConfig {
+    fn default() -> Self {
+        Self {
+            per_block: 1,
+            max_blocks: 4096,
+        }
+    }
 }
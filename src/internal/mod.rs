@@ -1,3 +1,8 @@
+pub mod action;
 pub mod client;
 pub mod config;
 pub mod network;
+pub mod rank;
+pub mod runner;
+pub mod server;
+pub mod source;
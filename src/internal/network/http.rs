@@ -2,17 +2,19 @@ extern crate hyper;
 extern crate reqwest;
 
 use async_trait::async_trait;
+use futures::future::join_all;
+use reqwest::header::{HeaderMap, HeaderValue, HOST};
 use reqwest::redirect::Policy;
 use reqwest::Method;
 use reqwest::StatusCode;
 use reqwest::Url;
 use std::error::Error;
-use std::io::ErrorKind;
 use std::net::SocketAddr;
 use std::str::FromStr;
+use std::sync::{Arc, Mutex};
 use std::time::Duration;
+use std::time::Instant;
 use std::time::SystemTime;
-use tokio::runtime::Runtime;
 
 use crate::internal::client::conn::ConnTest;
 use crate::internal::client::conn::ConnectTestStats;
@@ -21,16 +23,121 @@ use crate::internal::client::download::DownloadTest;
 use crate::internal::client::download::DownloadTestResult;
 use crate::internal::client::download::DownloadTestStats;
 use crate::internal::client::download::Speed;
+use crate::internal::client::download::SpeedFormat;
+use crate::internal::client::download::SpeedSeries;
+use crate::internal::client::retry::RetryPolicy;
+use crate::internal::client::upload::UploadTest;
+use crate::internal::client::upload::UploadTestResult;
+use crate::internal::client::upload::UploadTestStats;
+use crate::internal::network::tcp;
+use crate::internal::network::tls::{self, TlsOptions};
+
+// Cloudflare 支持的 http 和 https 端口
+const CF_HTTP_PORTS: [u16; 7] = [80, 8080, 8880, 2052, 2082, 2086, 2095];
+const CF_HTTPS_PORTS: [u16; 6] = [443, 2053, 2083, 2087, 2096, 8443];
+
+// 没有配置 request.user_agent 时使用的 User-Agent
+pub const DEFAULT_USER_AGENT: &str = "Mozilla/5.0 (Macintosh; Intel Mac OS X 10_12_6) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/98.0.4758.80 Safari/537.36";
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Scheme {
+    // 按端口判断，不是 Cloudflare 的标准端口时使用测试 URL 的协议
+    Auto,
+    Http,
+    Https,
+}
+
+impl Scheme {
+    pub fn for_port<'a>(&self, url: &'a Url, port: u16) -> &'a str {
+        match self {
+            Scheme::Http => "http",
+            Scheme::Https => "https",
+            Scheme::Auto if CF_HTTP_PORTS.contains(&port) => "http",
+            Scheme::Auto if CF_HTTPS_PORTS.contains(&port) => "https",
+            Scheme::Auto => url.scheme(),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Protocol {
+    // 由 ALPN 协商
+    Auto,
+    Http1,
+    Http2,
+    // 连通性测试由 ALPN 协商，之后对成功的地址进行 QUIC 探测；下载和上传测试不支持
+    Http3,
+}
+
+// HttpClient 的可选配置
+#[derive(Clone, Debug)]
+pub struct HttpOptions {
+    pub scheme: Scheme,
+    pub protocol: Protocol,
+    // 下载测试中每个 IP 同时下载的连接数
+    pub streams: usize,
+    // 下载速度的采样间隔，为 0 时不采样
+    pub sample_interval: Duration,
+    // 下载开始后不计入平均速度的预热时间
+    pub warmup: Duration,
+    // 下载时测试延迟的间隔，为 0 时不测试
+    pub latency_probe: Duration,
+    // 测试延迟时每次连接的超时时间
+    pub probe_timeout: Duration,
+    // 连通性测试中每个地址的测试次数
+    pub pings: usize,
+    // 连接和下载失败时的重试策略
+    pub retry: RetryPolicy,
+    // 所有请求使用的 User-Agent 和附加的请求头（包括 Cookie）
+    pub user_agent: String,
+    pub headers: HeaderMap,
+    // TLS 握手使用的 SNI，为空时使用测试 URL 中的主机
+    pub sni: Option<String>,
+    // 代替 Host 请求头，设置了 sni 而没有设置 host 时使用测试 URL 中的主机
+    pub host: Option<String>,
+    pub tls: TlsOptions,
+    // 显示测速结果使用的速度格式
+    pub speed_format: SpeedFormat,
+}
+
+impl Default for HttpOptions {
+    fn default() -> Self {
+        Self {
+            scheme: Scheme::Auto,
+            protocol: Protocol::Auto,
+            streams: 1,
+            sample_interval: Duration::from_millis(250),
+            warmup: Duration::from_millis(1000),
+            latency_probe: Duration::ZERO,
+            probe_timeout: Duration::from_secs(10),
+            pings: 1,
+            retry: RetryPolicy::default(),
+            user_agent: DEFAULT_USER_AGENT.to_string(),
+            headers: HeaderMap::new(),
+            sni: None,
+            host: None,
+            tls: TlsOptions::default(),
+            speed_format: SpeedFormat::default(),
+        }
+    }
+}
 
 pub struct HttpClient {
     vias: Vec<ServerAddress>,
     remote: ServerAddress,
     timeout: Duration,
     top: usize,
+    options: HttpOptions,
 }
 
 impl HttpClient {
-    pub fn build(url: Url, addrs: Vec<SocketAddr>, timeout: Duration, top: usize) -> Self {
+    pub fn build(
+        url: Url,
+        addrs: Vec<SocketAddr>,
+        timeout: Duration,
+        top: usize,
+        options: HttpOptions,
+    ) -> Self {
         let mut vias = Vec::new();
         for addr in addrs {
             vias.push(ServerAddress::Socket(addr))
@@ -42,10 +149,296 @@ impl HttpClient {
             remote,
             timeout,
             top,
+            options,
+        }
+    }
+
+    // 实际请求的 url：协议按 scheme 确定，端口使用代理地址的端口
+    // url 中的主机是 ip 时直接替换为代理地址，否则通过 resolve 指向代理地址
+    // 设置了 sni 时主机替换为 sni，请求的 Host 由 client_builder 设置
+    pub fn target_url(&self, remote: &Url, proxy: SocketAddr) -> Url {
+        let mut target = remote.clone();
+        let scheme = self
+            .options
+            .scheme
+            .for_port(remote, proxy.port())
+            .to_string();
+        let _ = target.set_scheme(&scheme);
+        let _ = target.set_port(Some(proxy.port()));
+        if let Some(sni) = &self.options.sni {
+            let _ = target.set_host(Some(sni));
+        }
+        if !matches!(target.host(), Some(url::Host::Domain(_))) {
+            let _ = target.set_ip_host(proxy.ip());
+        }
+        target
+    }
+
+    // 代替 Host 请求头的主机，设置了 sni 而没有设置 host 时使用测试 URL 中的主机
+    fn host_header(&self, remote: &Url) -> Option<String> {
+        match (&self.options.host, &self.options.sni) {
+            (Some(host), _) => Some(host.clone()),
+            (None, Some(_)) => remote.host_str().map(|x| x.to_string()),
+            (None, None) => None,
+        }
+    }
+
+    // Host 请求头和 SNI（即请求 URL 中的主机）不同
+    fn host_rewritten(&self, remote: &Url) -> bool {
+        let sni = self.options.sni.as_deref().or(remote.host_str());
+        matches!(self.host_header(remote), Some(host) if Some(host.as_str()) != sni)
+    }
+
+    // 所有请求共用的 client 设置，remote 为替换之前的测试 URL
+    fn client_builder(&self, remote: &Url, duration: Duration) -> reqwest::ClientBuilder {
+        let mut headers = self.options.headers.clone();
+        if let Some(host) = self
+            .host_header(remote)
+            .and_then(|x| HeaderValue::from_str(&x).ok())
+        {
+            headers.insert(HOST, host);
+        }
+        let builder = reqwest::Client::builder()
+            .connect_timeout(duration)
+            .default_headers(headers);
+        let builder = self.options.tls.apply(builder);
+        match self.options.user_agent.is_empty() {
+            true => builder,
+            false => builder.user_agent(self.options.user_agent.as_str()),
+        }
+    }
+
+    // 按 protocol 固定使用的 http 版本
+    // HTTP/2 按 URL 中的主机发送 :authority，Host 请求头和 SNI 不同时只能使用 HTTP/1.1
+    fn pin_protocol(
+        &self,
+        builder: reqwest::ClientBuilder,
+        remote: &Url,
+    ) -> reqwest::ClientBuilder {
+        match self.protocol(remote) {
+            Protocol::Http1 => builder.http1_only(),
+            Protocol::Http2 => builder.http2_prior_knowledge(),
+            Protocol::Auto | Protocol::Http3 => builder,
+        }
+    }
+
+    // 实际使用的协议，Host 与 SNI 不同的 http2 在检查配置时已拒绝
+    fn protocol(&self, remote: &Url) -> Protocol {
+        match self.options.protocol {
+            // HTTP/2 的 :authority 无法代替，Host 与 SNI 不同时只能使用 HTTP/1.1
+            Protocol::Auto | Protocol::Http3 if self.host_rewritten(remote) => Protocol::Http1,
+            x => x,
+        }
+    }
+
+    // 下载测试使用的 client，最多跟随 10 次重定向
+    fn download_client(
+        &self,
+        remote: &Url,
+        target: &Url,
+        proxy: SocketAddr,
+        duration: Duration,
+    ) -> reqwest::Client {
+        let mut client_builder = self
+            .client_builder(remote, duration)
+            .timeout(duration)
+            .redirect(Policy::custom(|attempt| {
+                if attempt.previous().len() > 10 {
+                    return attempt.error("too many redirects");
+                }
+                attempt.follow()
+            }));
+
+        client_builder = self.resolve(client_builder, target, proxy);
+        client_builder = self.pin_protocol(client_builder, remote);
+        client_builder.build().unwrap()
+    }
+
+    // resolve 会忽略端口，端口需要通过 target_url 写到 url 中
+    fn resolve(
+        &self,
+        builder: reqwest::ClientBuilder,
+        target: &Url,
+        proxy: SocketAddr,
+    ) -> reqwest::ClientBuilder {
+        match target.host() {
+            Some(url::Host::Domain(host)) => builder.resolve(host, proxy),
+            _ => builder,
+        }
+    }
+}
+
+// cf-ray 响应头形如 7d1a2b3c4d5e6f70-LAX，横线后面是数据中心代码
+fn colo(resp: &reqwest::Response) -> Option<String> {
+    let ray = resp.headers().get("cf-ray")?.to_str().ok()?;
+    ray.rsplit_once('-').map(|(_, colo)| colo.to_string())
+}
+
+// 上传进度，start 为开始发送请求体的时间，last 为最后一块数据写完的时间
+#[derive(Default)]
+struct UploadProgress {
+    start: Option<Instant>,
+    last: Option<Instant>,
+    sent: usize,
+}
+
+// 上传的请求体，按块生成 size 字节的数据
+// 上一块数据写入连接之后才会请求下一块，所以请求下一块（或者结束）时才把上一块计入 sent
+fn upload_body(size: usize, progress: Arc<Mutex<UploadProgress>>) -> reqwest::Body {
+    const CHUNK_SIZE: usize = 64 << 10;
+    let chunks = futures::stream::unfold((0, 0), move |(offset, pending)| {
+        let progress = progress.clone();
+        async move {
+            {
+                let now = Instant::now();
+                let mut progress = progress.lock().unwrap();
+                progress.start.get_or_insert(now);
+                if pending > 0 {
+                    progress.sent += pending;
+                    progress.last = Some(now);
+                }
+            }
+            if offset >= size {
+                return None;
+            }
+            let len = CHUNK_SIZE.min(size - offset);
+            Some((Ok::<_, std::io::Error>(vec![0u8; len]), (offset + len, len)))
+        }
+    });
+    reqwest::Body::wrap_stream(chunks)
+}
+
+// 按已经写入连接的数据计算上传速度，不包括建立连接和等待响应的时间
+fn upload_speed(progress: &Mutex<UploadProgress>) -> Option<Speed> {
+    let progress = progress.lock().unwrap();
+    let cost = progress.last?.duration_since(progress.start?);
+    match progress.sent > 0 && !cost.is_zero() {
+        true => Some(Speed::byte_per_second(progress.sent, cost)),
+        false => None,
+    }
+}
+
+// 一个下载连接的结果
+struct Fetched {
+    // 预热之后下载的字节数和耗时，下载在预热阶段就结束时为全部
+    bytes: usize,
+    cost: Duration,
+    series: SpeedSeries,
+    colo: Option<String>,
+    protocol: String,
+}
+
+// 下载 target 直到结束或者超过 duration，按 sample_interval 记录每个间隔的速度
+async fn fetch(
+    client: &reqwest::Client,
+    target: &Url,
+    duration: Duration,
+    options: &HttpOptions,
+) -> Result<Fetched, Box<dyn Error>> {
+    let mut resp = client.request(Method::GET, target.clone()).send().await?;
+    if resp.status() != StatusCode::OK {
+        return Err(Box::new(std::io::Error::other(format!(
+            "status {}",
+            resp.status()
+        ))));
+    }
+
+    let colo = colo(&resp);
+    let protocol = protocol(&resp);
+    let interval = options.sample_interval;
+    let warmup = options.warmup;
+    let now = SystemTime::now();
+    let mut bytes = 0;
+    let mut warm_bytes = 0;
+    let mut sample_bytes = 0;
+    let mut samples = Vec::new();
+    let mut cost = Duration::ZERO;
+    while cost < duration {
+        // 下载完成或者请求达到超时时间时按已经下载的数据计算，其他错误（如连接被重置）视为下载失败
+        let chunk = match resp.chunk().await {
+            Ok(Some(chunk)) => chunk,
+            Ok(None) => break,
+            Err(e) if e.is_timeout() => break,
+            Err(e) => return Err(Box::new(e)),
+        };
+        cost = now.elapsed().unwrap();
+
+        // 先结束已经过去的采样间隔，这次收到的数据算在当前间隔里
+        if !interval.is_zero() {
+            while interval * (samples.len() as u32 + 1) <= cost {
+                samples.push(sample_bytes as f64 / interval.as_secs_f64());
+                sample_bytes = 0;
+            }
+        }
+        if cost < warmup {
+            warm_bytes += chunk.len();
+        }
+        sample_bytes += chunk.len();
+        bytes += chunk.len();
+    }
+
+    let cost = now.elapsed().unwrap();
+    if !interval.is_zero() {
+        while interval * (samples.len() as u32 + 1) <= cost {
+            samples.push(sample_bytes as f64 / interval.as_secs_f64());
+            sample_bytes = 0;
+        }
+        // 最后一个不完整的间隔按实际经过的时间计算
+        let rest = cost - interval * samples.len() as u32;
+        if sample_bytes > 0 && !rest.is_zero() {
+            samples.push(sample_bytes as f64 / rest.as_secs_f64());
+        }
+    }
+    let series = SpeedSeries {
+        interval: interval.as_secs_f64(),
+        warmup: match interval.is_zero() {
+            true => 0,
+            false => (warmup.as_secs_f64() / interval.as_secs_f64()).ceil() as usize,
+        },
+        samples,
+    };
+    let (bytes, cost) = match cost > warmup && bytes > warm_bytes {
+        true => (bytes - warm_bytes, cost - warmup),
+        false => (bytes, cost),
+    };
+    Ok(Fetched {
+        bytes,
+        cost,
+        series,
+        colo,
+        protocol,
+    })
+}
+
+// 空闲时测试延迟的次数
+const IDLE_PROBES: usize = 3;
+
+fn median(mut x: Vec<Duration>) -> Option<Duration> {
+    x.sort();
+    x.get(x.len() / 2).copied()
+}
+
+// 每隔 interval 测试一次到 addr 的延迟并记录到 samples 中，直到任务被取消
+async fn probe_loop(
+    addr: SocketAddr,
+    interval: Duration,
+    timeout: Duration,
+    samples: Arc<Mutex<Vec<Duration>>>,
+) {
+    loop {
+        let start = Instant::now();
+        if let Some(cost) = tcp::probe(addr, timeout).await {
+            samples.lock().unwrap().push(cost);
         }
+        tokio::time::sleep(interval.saturating_sub(start.elapsed())).await;
     }
 }
 
+// 实际使用的 http 版本，如 HTTP/1.1、HTTP/2.0
+fn protocol(resp: &reqwest::Response) -> String {
+    format!("{:?}", resp.version())
+}
+
 #[async_trait]
 impl ConnTest for HttpClient {
     async fn connect(
@@ -57,19 +450,12 @@ impl ConnTest for HttpClient {
         let proxy_host = match remote {
             ServerAddress::Socket(socket) => socket,
             ServerAddress::URL(url) => {
-                return Err(Box::new(std::io::Error::new(
-                    ErrorKind::Other,
-                    format!("invalid via address {url}"),
-                )))
+                return Err(Box::new(std::io::Error::other(format!(
+                    "invalid via address {url}"
+                ))))
             }
         };
 
-        let mut client_builder = reqwest::Client::builder()
-            .connect_timeout(duration)
-            .timeout(duration)
-            .connection_verbose(true)
-            .user_agent("Mozilla/5.0 (Macintosh; Intel Mac OS X 10_12_6) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/98.0.4758.80 Safari/537.36");
-
         let remote = match via {
             Some(ServerAddress::URL(url)) => url,
             Some(ServerAddress::Socket(socket)) => {
@@ -77,17 +463,19 @@ impl ConnTest for HttpClient {
                     .unwrap()
             }
             None => {
-                return Err(Box::new(std::io::Error::new(
-                    ErrorKind::Other,
-                    format!("remote address not found"),
+                return Err(Box::new(std::io::Error::other(
+                    "remote address not found".to_string(),
                 )))
             }
         };
 
-        if !proxy_host.ip().is_loopback() {
-            client_builder =
-                client_builder.resolve(remote.clone().host_str().unwrap(), proxy_host.clone());
-        }
+        let mut client_builder = self
+            .client_builder(&remote, duration)
+            .timeout(duration)
+            .connection_verbose(true);
+        client_builder = self.pin_protocol(client_builder, &remote);
+        let remote = self.target_url(&remote, proxy_host);
+        client_builder = self.resolve(client_builder, &remote, proxy_host);
 
         let client = client_builder.build().unwrap();
         let now = SystemTime::now();
@@ -98,12 +486,15 @@ impl ConnTest for HttpClient {
         match result {
             Ok(resp) => match resp.status() {
                 StatusCode::OK | StatusCode::MOVED_PERMANENTLY | StatusCode::FOUND => {
-                    Ok(ConnectTestStats::new(proxy_host.ip(), cost))
+                    let mut stats = ConnectTestStats::new(proxy_host, cost);
+                    stats.colo = colo(&resp);
+                    stats.protocol = Some(protocol(&resp));
+                    Ok(stats)
                 }
-                _ => Err(Box::new(std::io::Error::new(
-                    std::io::ErrorKind::Other,
-                    format!("status {}", resp.status()),
-                ))),
+                _ => Err(Box::new(std::io::Error::other(format!(
+                    "status {}",
+                    resp.status()
+                )))),
             },
             Err(e) => Err(Box::new(e)),
         }
@@ -124,6 +515,14 @@ impl ConnTest for HttpClient {
     fn get_top(&self) -> usize {
         self.top
     }
+
+    fn get_pings(&self) -> usize {
+        self.options.pings
+    }
+
+    fn get_retry(&self) -> RetryPolicy {
+        self.options.retry.clone()
+    }
 }
 
 impl DownloadTest for HttpClient {
@@ -141,6 +540,7 @@ impl DownloadTest for HttpClient {
                 return DownloadTestResult {
                     top: self.top,
                     list: None,
+                    failed: Vec::new(),
                 }
             }
         };
@@ -149,7 +549,12 @@ impl DownloadTest for HttpClient {
             "开始测试下载速度。程序会测试直到有 {} 条有效的下载数据为止，请耐心等待。",
             self.top
         );
+        let streams = self.options.streams.max(1);
+        if streams > 1 {
+            println!("每个 IP 先测试单连接下载速度，再测试 {streams} 个连接同时下载的速度");
+        }
         let mut stats = Vec::new();
+        let mut failed = Vec::new();
 
         let rt = tokio::runtime::Builder::new_multi_thread()
             .enable_all()
@@ -157,106 +562,225 @@ impl DownloadTest for HttpClient {
             .unwrap();
 
         for via in ips {
-            print!(
-                "正在测试从 {:?} 到 {} 的下载速度 ... ",
-                via,
-                remote.to_string()
-            );
+            print!("正在测试从 {:?} 到 {} 的下载速度 ... ", via, remote);
             let proxy_host = match via {
                 ServerAddress::Socket(socket) => socket,
 
                 ServerAddress::URL(url) => {
-                    println!("====> 无效(url: {})", url.to_string());
+                    println!("====> 无效(url: {})", url);
                     continue;
                 }
             };
 
-            let mut client_builder = reqwest::Client::builder()
-                .connect_timeout(duration)
-                .timeout(duration)
-                .user_agent("Mozilla/5.0 (Macintosh; Intel Mac OS X 10_12_6) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/98.0.4758.80 Safari/537.36")
-            .redirect(Policy::custom(|attempt| {
-                if attempt.previous().len() > 10 {
-                    return attempt.error("too many redirects");
-                }
-                attempt.follow()
-            }));
-
-            if !proxy_host.ip().is_loopback() {
-                client_builder =
-                    client_builder.resolve(remote.clone().host_str().unwrap(), proxy_host.clone());
-            }
-
-            let client = client_builder.build().unwrap();
+            let target = self.target_url(&remote, *proxy_host);
+            let client = self.download_client(&remote, &target, *proxy_host, duration);
             let start_conn = SystemTime::now();
 
+            // 下载前测试空闲延迟，下载时在后台测试负载下的延迟
+            let probe_interval = self.options.latency_probe;
+            let probe_timeout = self.options.probe_timeout;
+            let mut idle = Vec::new();
+            let retry = &self.options.retry;
+            let download = || fetch(&client, &target, duration, &self.options);
             let result = rt.block_on(async {
-                let req = client.request(Method::GET, remote.clone());
-                req.send().await
+                if probe_interval.is_zero() {
+                    return (retry.run(download).await, None);
+                }
+                for _ in 0..IDLE_PROBES {
+                    idle.extend(tcp::probe(*proxy_host, probe_timeout).await);
+                }
+                let loaded = Arc::new(Mutex::new(Vec::new()));
+                let probe = tokio::spawn(probe_loop(
+                    *proxy_host,
+                    probe_interval,
+                    probe_timeout,
+                    loaded.clone(),
+                ));
+                let fetched = retry.run(download).await;
+                // 下载结束后立即停止，不等待正在进行的探测
+                probe.abort();
+                let loaded = std::mem::take(&mut *loaded.lock().unwrap());
+                (fetched, Some(loaded))
             });
 
-            let conn_cost = start_conn.elapsed().unwrap();
-            if let Ok(mut resp) = result {
-                if resp.status() == StatusCode::OK {
-                    let now = SystemTime::now();
-
-                    let mut total_data = 0;
-                    let mut total_cost = Duration::ZERO;
-                    loop {
-                        if total_cost >= duration {
-                            break;
-                        }
-
-                        let result = rt.block_on(async { resp.chunk().await.unwrap() });
-                        total_cost = now.elapsed().unwrap();
-
-                        match result {
-                            None => break,
-                            Some(data) => {
-                                total_data += data.len();
-                            }
-                        };
-                    }
-
-                    let mut speed = Speed::byte_per_second(total_data, total_cost);
-                    speed.mb();
-                    stats.push(DownloadTestStats::new(
-                        resp.remote_addr().unwrap().ip(),
-                        speed,
-                    ));
-
-                    println!(
-                        "===> 有效({}:{:?}, {:?})",
-                        stats.len(),
-                        conn_cost,
-                        total_cost
-                    );
-                    if stats.len() >= self.top {
-                        println!("测试下载速度结束。");
-                        break;
-                    }
-
+            let (fetched, retries) = result.0;
+            let fetched = match fetched {
+                Ok(fetched) => fetched,
+                Err(_) => {
+                    println!("===> 无效({:?})", start_conn.elapsed().unwrap());
+                    failed.push(*proxy_host);
                     continue;
                 }
+            };
+
+            let speed = Speed::byte_per_second(fetched.bytes, fetched.cost);
+            let mut stat = DownloadTestStats::new(*proxy_host, speed);
+            stat.series = fetched.series;
+            stat.idle_latency = median(idle);
+            stat.loaded_latency = result.1.and_then(median);
+            stat.colo = fetched.colo;
+            stat.protocol = Some(fetched.protocol);
+            stat.retries = retries;
+            stat.format = self.options.speed_format;
+            if self.options.tls.inspect && target.scheme() == "https" {
+                let sni = target.host_str().unwrap_or_default();
+                let protocol = self.protocol(&remote);
+                match tls::inspect(*proxy_host, sni, duration, &self.options.tls, protocol) {
+                    Ok(info) => stat.tls = Some(info),
+                    Err(e) => println!("获取 TLS 信息失败({e})"),
+                }
+            }
+
+            // 每个连接使用单独的 client，保证不会复用同一个连接，每个连接同样按重试策略重试
+            if streams > 1 {
+                let clients: Vec<_> = (0..streams)
+                    .map(|_| self.download_client(&remote, &target, *proxy_host, duration))
+                    .collect();
+                let results = rt.block_on(join_all(
+                    clients
+                        .iter()
+                        .map(|x| retry.run(|| fetch(x, &target, duration, &self.options))),
+                ));
+                stat.retries += results.iter().map(|x| x.1).sum::<usize>();
+                // 重试后仍有连接失败时多连接的速度没有意义，只保留单连接的结果
+                let results: Vec<_> = results.into_iter().filter_map(|x| x.0.ok()).collect();
+                let bytes: usize = results.iter().map(|x| x.bytes).sum();
+                let cost = results.iter().map(|x| x.cost).max().unwrap_or_default();
+                if results.len() < streams {
+                    print!("多连接下载只有 {}/{streams} 个连接成功 ", results.len());
+                } else if !cost.is_zero() {
+                    let multi = Speed::byte_per_second(bytes, cost);
+                    stat.single_speed = Some(std::mem::replace(&mut stat.speed, multi));
+                    let series: Vec<_> = results.into_iter().map(|x| x.series).collect();
+                    stat.series = SpeedSeries::merge(&series);
+                }
+            }
+            stats.push(stat);
 
-                println!("===> 无效({:?})", conn_cost);
-                continue;
+            println!(
+                "===> 有效({}:{:?}, {:?})",
+                stats.len(),
+                start_conn.elapsed().unwrap(),
+                fetched.cost
+            );
+            if stats.len() >= self.top {
+                println!("测试下载速度结束。");
+                break;
             }
-            println!("===> 无效({:?})", conn_cost);
         }
 
-        stats.sort_by(|x, y| x.speed.partial_cmp(&y.speed).unwrap());
-        stats.reverse();
-        if stats.len() == 0 {
+        stats.sort_by_key(|x| std::cmp::Reverse(x.speed));
+        if stats.is_empty() {
             return DownloadTestResult {
                 top: self.top,
                 list: None,
+                failed,
             };
         }
 
         DownloadTestResult {
             top: self.top,
             list: Some(stats),
+            failed,
+        }
+    }
+}
+
+// 上传测试，通过 HttpClient 的各个地址 POST size 字节的数据
+pub struct UploadClient {
+    client: HttpClient,
+    size: usize,
+}
+
+impl UploadClient {
+    pub fn build(client: HttpClient, size: usize) -> Self {
+        Self { client, size }
+    }
+}
+
+impl UploadTest for UploadClient {
+    fn upload_test(&self) -> UploadTestResult {
+        let http = &self.client;
+        let duration = http.get_timeout();
+        let size = self.size;
+
+        let remote = match http.get_address_remote() {
+            Some(ServerAddress::URL(url)) => url,
+            _ => {
+                return UploadTestResult {
+                    top: http.top,
+                    list: None,
+                    failed: Vec::new(),
+                }
+            }
+        };
+
+        println!("开始测试上传速度，每个 IP 上传 {size} 字节，请耐心等待。");
+        let mut stats = Vec::new();
+        let mut failed = Vec::new();
+
+        let rt = tokio::runtime::Builder::new_multi_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+
+        for via in &http.vias {
+            print!("正在测试从 {:?} 到 {} 的上传速度 ... ", via, remote);
+            let proxy_host = match via {
+                ServerAddress::Socket(socket) => socket,
+                ServerAddress::URL(url) => {
+                    println!("====> 无效(url: {})", url);
+                    continue;
+                }
+            };
+
+            let mut client_builder = http.client_builder(&remote, duration);
+
+            let target = http.target_url(&remote, *proxy_host);
+            client_builder = http.resolve(client_builder, &target, *proxy_host);
+            client_builder = http.pin_protocol(client_builder, &remote);
+
+            let client = client_builder.build().unwrap();
+            let progress = Arc::new(Mutex::new(UploadProgress::default()));
+            let body = upload_body(size, progress.clone());
+            let now = SystemTime::now();
+            let result = rt.block_on(async {
+                let req = client.request(Method::POST, target.clone()).body(body);
+                tokio::time::timeout(duration, req.send()).await
+            });
+            let cost = now.elapsed().unwrap();
+
+            let speed = upload_speed(&progress);
+            let mut stat = match (result, speed) {
+                (Ok(Ok(resp)), Some(speed)) if resp.status().is_success() => {
+                    let mut stat = UploadTestStats::new(*proxy_host, speed);
+                    stat.colo = colo(&resp);
+                    stat.protocol = Some(protocol(&resp));
+                    stat
+                }
+                // 超时的时候按已经写入连接的数据计算
+                (Err(_), Some(speed)) => UploadTestStats::new(*proxy_host, speed),
+                _ => {
+                    println!("===> 无效({:?})", cost);
+                    failed.push(*proxy_host);
+                    continue;
+                }
+            };
+            stat.format = self.client.options.speed_format;
+
+            stats.push(stat);
+            println!("===> 有效({}:{:?})", stats.len(), cost);
+            if stats.len() >= http.top {
+                println!("测试上传速度结束。");
+                break;
+            }
+        }
+
+        stats.sort_by_key(|x| std::cmp::Reverse(x.speed));
+        UploadTestResult {
+            top: http.top,
+            list: if stats.is_empty() { None } else { Some(stats) },
+            failed,
         }
     }
 }
@@ -1,3 +1,5 @@
 pub mod api;
 pub mod http;
+pub mod quic;
 pub mod tcp;
+pub mod tls;
@@ -0,0 +1,172 @@
+extern crate tokio;
+
+use crate::internal::client::conn::{ConnTest, ConnectTestStats};
+use crate::internal::client::def::ServerAddress;
+use crate::internal::client::retry::RetryPolicy;
+
+use async_trait::async_trait;
+use std::collections::hash_map::RandomState;
+use std::error::Error;
+use std::hash::{BuildHasher, Hasher};
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::time::{Duration, SystemTime};
+use tokio::{net::UdpSocket, time};
+
+// 服务器只响应不小于 1200 字节的 Initial 包
+const PROBE_SIZE: usize = 1200;
+// 保留的版本号（形如 0x?a?a?a?a），服务器收到后必须回复版本协商包
+const PROBE_VERSION: u32 = 0x1a2a_3a4a;
+const QUIC_V1: u32 = 1;
+
+// 随机的 8 字节连接 ID
+fn connection_id() -> [u8; 8] {
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u128(
+        SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos(),
+    );
+    hasher.finish().to_be_bytes()
+}
+
+// 使用保留版本号的长包头 Initial 包，用来触发版本协商
+pub fn probe_packet(dcid: &[u8], scid: &[u8]) -> Vec<u8> {
+    let mut packet = vec![0xc0];
+    packet.extend_from_slice(&PROBE_VERSION.to_be_bytes());
+    packet.push(dcid.len() as u8);
+    packet.extend_from_slice(dcid);
+    packet.push(scid.len() as u8);
+    packet.extend_from_slice(scid);
+    packet.resize(PROBE_SIZE, 0);
+    packet
+}
+
+// 解析版本协商包，返回服务器支持的版本列表
+// 版本协商包的目标连接 ID 是探测包的源连接 ID
+pub fn parse_version_negotiation(packet: &[u8], scid: &[u8]) -> Option<Vec<u32>> {
+    if packet.len() < 7 || packet[0] & 0x80 == 0 || packet[1..5] != [0, 0, 0, 0] {
+        return None;
+    }
+    let dcid_len = packet[5] as usize;
+    let dcid = packet.get(6..6 + dcid_len)?;
+    if dcid != scid {
+        return None;
+    }
+    let scid_len = *packet.get(6 + dcid_len)? as usize;
+    let versions = packet.get(7 + dcid_len + scid_len..)?;
+    Some(
+        versions
+            .chunks_exact(4)
+            .map(|x| u32::from_be_bytes([x[0], x[1], x[2], x[3]]))
+            .collect(),
+    )
+}
+
+// 通过 UDP 上的 QUIC 版本协商测试连通性，不建立完整的 HTTP/3 连接
+pub struct QuicClient {
+    addrs: Vec<ServerAddress>,
+    timeout: Duration,
+    top: usize,
+    pings: usize,
+    retry: RetryPolicy,
+}
+
+impl QuicClient {
+    pub fn build(
+        src: Vec<SocketAddr>,
+        timeout: Duration,
+        top: usize,
+        pings: usize,
+        retry: RetryPolicy,
+    ) -> Self {
+        let mut addrs = Vec::new();
+        for addr in src {
+            addrs.push(ServerAddress::Socket(addr));
+        }
+        Self {
+            addrs,
+            timeout,
+            top,
+            pings,
+            retry,
+        }
+    }
+}
+
+#[async_trait]
+impl ConnTest for QuicClient {
+    async fn connect(
+        &self,
+        dst: ServerAddress,
+        _: Option<ServerAddress>,
+        timeout: Duration,
+    ) -> Result<ConnectTestStats, Box<dyn Error>> {
+        let socket_addr = match dst {
+            ServerAddress::Socket(socket) => socket,
+            ServerAddress::URL(url) => {
+                return Err(Box::new(std::io::Error::other(format!(
+                    "invalid dst {url}"
+                ))))
+            }
+        };
+
+        let local: SocketAddr = match socket_addr {
+            SocketAddr::V4(_) => (Ipv4Addr::UNSPECIFIED, 0).into(),
+            SocketAddr::V6(_) => (Ipv6Addr::UNSPECIFIED, 0).into(),
+        };
+        let socket = UdpSocket::bind(local).await?;
+        socket.connect(socket_addr).await?;
+
+        let dcid = connection_id();
+        let scid = connection_id();
+        let packet = probe_packet(&dcid, &scid);
+
+        let now = SystemTime::now();
+        let result = time::timeout(timeout, async {
+            socket.send(&packet).await?;
+            let mut buf = [0u8; 1500];
+            loop {
+                let n = socket.recv(&mut buf).await?;
+                if let Some(versions) = parse_version_negotiation(&buf[..n], &scid) {
+                    return Ok::<_, std::io::Error>(versions);
+                }
+            }
+        })
+        .await;
+        let cost = now.elapsed().unwrap();
+
+        let versions = result??;
+        let mut stats = ConnectTestStats::new(socket_addr, cost);
+        // 版本协商只能说明服务器支持哪些 QUIC 版本，不代表 HTTP/3 可用
+        stats.protocol = Some(match versions.contains(&QUIC_V1) {
+            true => "QUIC v1".to_string(),
+            false => "QUIC".to_string(),
+        });
+        Ok(stats)
+    }
+
+    fn get_address_conn(&self) -> Vec<ServerAddress> {
+        self.addrs.clone()
+    }
+
+    fn get_address_remote(&self) -> Option<ServerAddress> {
+        None
+    }
+
+    fn get_timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    fn get_top(&self) -> usize {
+        self.top
+    }
+
+    fn get_pings(&self) -> usize {
+        self.pings
+    }
+
+    fn get_retry(&self) -> RetryPolicy {
+        self.retry.clone()
+    }
+}
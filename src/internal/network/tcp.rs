@@ -3,20 +3,38 @@ extern crate tokio;
 
 use crate::internal::client::conn::{ConnTest, ConnectTestStats};
 use crate::internal::client::def::ServerAddress;
+use crate::internal::client::retry::RetryPolicy;
 
 use async_trait::async_trait;
 use std::error::Error;
 use std::{net::SocketAddr, time::Duration};
 use tokio::{net::TcpStream, time};
 
+// 建立一次 TCP 连接的耗时，连接失败或超时时返回 None
+pub async fn probe(addr: SocketAddr, timeout: Duration) -> Option<Duration> {
+    let now = std::time::Instant::now();
+    match time::timeout(timeout, TcpStream::connect(addr)).await {
+        Ok(Ok(_)) => Some(now.elapsed()),
+        _ => None,
+    }
+}
+
 pub struct TcpClient {
     addrs: Vec<ServerAddress>,
     timeout: Duration,
     top: usize,
+    pings: usize,
+    retry: RetryPolicy,
 }
 
 impl TcpClient {
-    pub fn build(src: Vec<SocketAddr>, timeout: Duration, top: usize) -> Self {
+    pub fn build(
+        src: Vec<SocketAddr>,
+        timeout: Duration,
+        top: usize,
+        pings: usize,
+        retry: RetryPolicy,
+    ) -> Self {
         let mut addrs = Vec::new();
         for addr in src {
             addrs.push(ServerAddress::Socket(addr));
@@ -25,6 +43,8 @@ impl TcpClient {
             addrs,
             timeout,
             top,
+            pings,
+            retry,
         }
     }
 }
@@ -40,22 +60,21 @@ impl ConnTest for TcpClient {
         let socket_addr = match dst {
             ServerAddress::Socket(socket) => socket,
             ServerAddress::URL(url) => {
-                return Err(Box::new(std::io::Error::new(
-                    std::io::ErrorKind::Other,
-                    format!("invalid dst {url}"),
-                )))
+                return Err(Box::new(std::io::Error::other(format!(
+                    "invalid dst {url}"
+                ))))
             }
         };
 
         let now = std::time::SystemTime::now();
-        let conn_result = time::timeout(timeout, TcpStream::connect(socket_addr.clone())).await;
+        let conn_result = time::timeout(timeout, TcpStream::connect(socket_addr)).await;
         let cost = now.elapsed().unwrap();
 
         match conn_result {
             Ok(result) => match result {
                 Ok(stream) => {
                     drop(stream);
-                    Ok(ConnectTestStats::new(socket_addr.ip(), cost))
+                    Ok(ConnectTestStats::new(socket_addr, cost))
                 }
 
                 Err(e) => Err(Box::new(e)),
@@ -80,4 +99,12 @@ impl ConnTest for TcpClient {
     fn get_top(&self) -> usize {
         self.top
     }
+
+    fn get_pings(&self) -> usize {
+        self.pings
+    }
+
+    fn get_retry(&self) -> RetryPolicy {
+        self.retry.clone()
+    }
 }
@@ -0,0 +1,200 @@
+use crate::internal::client::def::TlsInfo;
+use crate::internal::network::http::Protocol;
+
+#[cfg(feature = "tls-inspect")]
+use openssl::asn1::Asn1Time;
+#[cfg(feature = "tls-inspect")]
+use openssl::nid::Nid;
+#[cfg(feature = "tls-inspect")]
+use openssl::pkey::PKey;
+#[cfg(feature = "tls-inspect")]
+use openssl::ssl::{SslConnector, SslMethod, SslVerifyMode, SslVersion};
+#[cfg(feature = "tls-inspect")]
+use openssl::x509::X509;
+use std::error::Error;
+use std::net::SocketAddr;
+#[cfg(feature = "tls-inspect")]
+use std::net::TcpStream;
+use std::time::Duration;
+
+// 可以设置的最低 TLS 版本，reqwest 使用 native-tls 时不支持要求 TLS 1.3
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TlsVersion {
+    Tls10,
+    Tls11,
+    Tls12,
+}
+
+impl TlsVersion {
+    pub fn parse(s: &str) -> Result<Self, Box<dyn Error>> {
+        match s {
+            "1.0" => Ok(Self::Tls10),
+            "1.1" => Ok(Self::Tls11),
+            "1.2" => Ok(Self::Tls12),
+            others => Err(format!("invalid tls version: {others}").into()),
+        }
+    }
+
+    pub fn to_reqwest(self) -> reqwest::tls::Version {
+        match self {
+            Self::Tls10 => reqwest::tls::Version::TLS_1_0,
+            Self::Tls11 => reqwest::tls::Version::TLS_1_1,
+            Self::Tls12 => reqwest::tls::Version::TLS_1_2,
+        }
+    }
+
+    #[cfg(feature = "tls-inspect")]
+    fn to_openssl(self) -> SslVersion {
+        match self {
+            Self::Tls10 => SslVersion::TLS1,
+            Self::Tls11 => SslVersion::TLS1_1,
+            Self::Tls12 => SslVersion::TLS1_2,
+        }
+    }
+}
+
+// HttpClient 的 TLS 选项
+#[derive(Clone, Debug)]
+pub struct TlsOptions {
+    // 为 false 时不验证服务器证书
+    pub verify: bool,
+    // 额外信任的 CA 证书，以及 inspect 使用的 PEM 格式的证书文件
+    pub ca: Vec<reqwest::Certificate>,
+    #[cfg(feature = "tls-inspect")]
+    pub ca_pem: Option<Vec<u8>>,
+    // 客户端证书，以及 inspect 使用的 PEM 格式的证书和私钥
+    pub identity: Option<reqwest::Identity>,
+    #[cfg(feature = "tls-inspect")]
+    pub identity_pem: Option<(Vec<u8>, Vec<u8>)>,
+    pub min_version: Option<TlsVersion>,
+    // 下载测试后是否单独握手一次，记录 TLS 版本和证书信息
+    pub inspect: bool,
+}
+
+impl Default for TlsOptions {
+    fn default() -> Self {
+        Self {
+            verify: true,
+            ca: Vec::new(),
+            #[cfg(feature = "tls-inspect")]
+            ca_pem: None,
+            identity: None,
+            #[cfg(feature = "tls-inspect")]
+            identity_pem: None,
+            min_version: None,
+            inspect: false,
+        }
+    }
+}
+
+impl TlsOptions {
+    pub fn apply(&self, mut builder: reqwest::ClientBuilder) -> reqwest::ClientBuilder {
+        builder = builder.danger_accept_invalid_certs(!self.verify);
+        for cert in &self.ca {
+            builder = builder.add_root_certificate(cert.clone());
+        }
+        if let Some(identity) = &self.identity {
+            builder = builder.identity(identity.clone());
+        }
+        if let Some(version) = self.min_version {
+            builder = builder.min_tls_version(version.to_reqwest());
+        }
+        builder
+    }
+}
+
+// 读取 PEM 文件中的全部证书
+pub fn parse_certificates(pem: &[u8]) -> Result<Vec<reqwest::Certificate>, Box<dyn Error>> {
+    const END: &str = "-----END CERTIFICATE-----";
+    let pem = std::str::from_utf8(pem)?;
+    let mut certs = Vec::new();
+    let mut rest = pem;
+    while let Some(start) = rest.find("-----BEGIN CERTIFICATE-----") {
+        let end = rest[start..].find(END).ok_or("证书缺少结束标记")? + start + END.len();
+        certs.push(reqwest::Certificate::from_pem(
+            &rest.as_bytes()[start..end],
+        )?);
+        rest = &rest[end..];
+    }
+    if certs.is_empty() {
+        return Err("没有找到 PEM 格式的证书".into());
+    }
+    Ok(certs)
+}
+
+// reqwest 不提供协商的 TLS 版本，单独与 addr 握手一次来获取 TLS 版本和服务器证书
+// 与下载测试的 client 一样按 verify 和 ca 验证证书，并按 protocol 提供相同的 ALPN 协议
+#[cfg(feature = "tls-inspect")]
+pub fn inspect(
+    addr: SocketAddr,
+    sni: &str,
+    timeout: Duration,
+    options: &TlsOptions,
+    protocol: Protocol,
+) -> Result<TlsInfo, Box<dyn Error>> {
+    let stream = TcpStream::connect_timeout(&addr, timeout)?;
+    stream.set_read_timeout(Some(timeout))?;
+    stream.set_write_timeout(Some(timeout))?;
+
+    let mut builder = SslConnector::builder(SslMethod::tls())?;
+    match options.verify {
+        true => {
+            builder.set_verify(SslVerifyMode::PEER);
+            if let Some(pem) = &options.ca_pem {
+                for cert in X509::stack_from_pem(pem)? {
+                    builder.cert_store_mut().add_cert(cert)?;
+                }
+            }
+        }
+        false => builder.set_verify(SslVerifyMode::NONE),
+    }
+    builder.set_alpn_protos(match protocol {
+        Protocol::Http1 => b"\x08http/1.1",
+        Protocol::Http2 => b"\x02h2",
+        Protocol::Auto | Protocol::Http3 => b"\x02h2\x08http/1.1",
+    })?;
+    builder.set_min_proto_version(options.min_version.map(|x| x.to_openssl()))?;
+    if let Some((cert, key)) = &options.identity_pem {
+        let cert = X509::from_pem(cert)?;
+        let key = PKey::private_key_from_pem(key)?;
+        builder.set_certificate(&cert)?;
+        builder.set_private_key(&key)?;
+    }
+    let mut config = builder.build().configure()?;
+    config.set_verify_hostname(options.verify);
+    config.set_use_server_name_indication(sni.parse::<std::net::IpAddr>().is_err());
+    let ssl = config.connect(sni, stream)?;
+
+    let cert = ssl
+        .ssl()
+        .peer_certificate()
+        .ok_or("server did not send a certificate")?;
+    let issuer = cert
+        .issuer_name()
+        .entries_by_nid(Nid::COMMONNAME)
+        .chain(cert.issuer_name().entries_by_nid(Nid::ORGANIZATIONNAME))
+        .next()
+        .and_then(|x| x.data().as_utf8().ok())
+        .map(|x| x.to_string())
+        .unwrap_or_default();
+    let days_left = Asn1Time::days_from_now(0)?.diff(cert.not_after())?.days;
+
+    Ok(TlsInfo {
+        version: ssl.ssl().version_str().to_string(),
+        issuer,
+        expires: cert.not_after().to_string(),
+        days_left,
+    })
+}
+
+// 没有启用 tls-inspect 功能时无法获取 TLS 信息
+#[cfg(not(feature = "tls-inspect"))]
+pub fn inspect(
+    _addr: SocketAddr,
+    _sni: &str,
+    _timeout: Duration,
+    _options: &TlsOptions,
+    _protocol: Protocol,
+) -> Result<TlsInfo, Box<dyn Error>> {
+    Err("没有启用 tls-inspect 功能".into())
+}
@@ -0,0 +1,178 @@
+use crate::internal::client::conn::ConnectTestResult;
+use crate::internal::client::download::{DownloadTestResult, DownloadTestStats};
+use crate::internal::config::def::{RankConfig, RankWeights};
+
+use std::error::Error;
+
+// 参与排序的一个地址的测速数据
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Candidate {
+    // 连接耗时（毫秒），没有连通性测试数据时为空
+    pub latency: Option<f64>,
+    // 丢包比例
+    pub loss: f64,
+    // 抖动（毫秒）
+    pub jitter: f64,
+    // 下载速度（字节/秒）
+    pub speed: f64,
+}
+
+pub trait Strategy {
+    // 分数越高排名越靠前，all 为参与排序的全部地址，用于归一化
+    fn score(&self, x: &Candidate, all: &[Candidate]) -> f64;
+
+    fn get_name(&self) -> &str;
+}
+
+// 只按延迟排序
+pub struct LatencyStrategy;
+
+impl Strategy for LatencyStrategy {
+    fn score(&self, x: &Candidate, all: &[Candidate]) -> f64 {
+        -x.latency.unwrap_or_else(|| worst_latency(all))
+    }
+
+    fn get_name(&self) -> &str {
+        "latency"
+    }
+}
+
+// 只按下载速度排序
+pub struct SpeedStrategy;
+
+impl Strategy for SpeedStrategy {
+    fn score(&self, x: &Candidate, _: &[Candidate]) -> f64 {
+        x.speed
+    }
+
+    fn get_name(&self) -> &str {
+        "speed"
+    }
+}
+
+// 先按延迟（取整到毫秒）排序，延迟相同时按下载速度排序
+// 分数的整数部分为负的延迟，小数部分为相对下载速度
+pub struct LexicographicStrategy;
+
+impl Strategy for LexicographicStrategy {
+    fn score(&self, x: &Candidate, all: &[Candidate]) -> f64 {
+        let latency = x.latency.unwrap_or_else(|| worst_latency(all)).round();
+        let max_speed = all.iter().map(|x| x.speed).fold(0.0, f64::max);
+        let speed = match max_speed > 0.0 {
+            true => x.speed / max_speed * 0.999,
+            false => 0.0,
+        };
+        -latency + speed
+    }
+
+    fn get_name(&self) -> &str {
+        "lexicographic"
+    }
+}
+
+// 各项指标归一化到 0~1 后加权，分数范围 0~100
+pub struct WeightedStrategy {
+    weights: RankWeights,
+}
+
+impl WeightedStrategy {
+    pub fn build(weights: RankWeights) -> Self {
+        Self { weights }
+    }
+}
+
+// 把 x 在 [min, max] 中的位置归一化到 0~1，越大越好；所有值相同时为 1
+fn normalize(x: f64, values: impl Iterator<Item = f64> + Clone, lower_better: bool) -> f64 {
+    let min = values.clone().fold(f64::INFINITY, f64::min);
+    let max = values.fold(f64::NEG_INFINITY, f64::max);
+    if max - min <= f64::EPSILON {
+        return 1.0;
+    }
+    match lower_better {
+        true => (max - x) / (max - min),
+        false => (x - min) / (max - min),
+    }
+}
+
+impl Strategy for WeightedStrategy {
+    fn score(&self, x: &Candidate, all: &[Candidate]) -> f64 {
+        let w = &self.weights;
+        let total = w.latency + w.loss + w.jitter + w.speed;
+        if total <= 0.0 {
+            return 0.0;
+        }
+
+        let latency = match x.latency {
+            Some(latency) => normalize(latency, all.iter().filter_map(|x| x.latency), true),
+            None => 0.0,
+        };
+        let loss = 1.0 - x.loss;
+        let jitter = normalize(x.jitter, all.iter().map(|x| x.jitter), true);
+        let speed = normalize(x.speed, all.iter().map(|x| x.speed), false);
+        100.0 * (w.latency * latency + w.loss * loss + w.jitter * jitter + w.speed * speed) / total
+    }
+
+    fn get_name(&self) -> &str {
+        "weighted"
+    }
+}
+
+// 没有延迟数据的地址按最慢处理
+fn worst_latency(all: &[Candidate]) -> f64 {
+    all.iter().filter_map(|x| x.latency).fold(0.0, f64::max) + 1.0
+}
+
+pub fn create_strategy(conf: &RankConfig) -> Result<Box<dyn Strategy>, Box<dyn Error>> {
+    match conf.strategy.as_str() {
+        "latency" => Ok(Box::new(LatencyStrategy)),
+        "speed" => Ok(Box::new(SpeedStrategy)),
+        "lexicographic" => Ok(Box::new(LexicographicStrategy)),
+        "weighted" => {
+            let w = &conf.weights;
+            let weights = [w.latency, w.loss, w.jitter, w.speed];
+            if weights.iter().any(|x| !x.is_finite() || *x < 0.0) {
+                return Err(format!("invalid rank weights: {weights:?}，权重不能为负数").into());
+            }
+            Ok(Box::new(WeightedStrategy::build(w.clone())))
+        }
+        others => Err(format!("invalid rank strategy: {others}").into()),
+    }
+}
+
+// 按 strategy 计算下载结果中每个地址的分数，并按分数从高到低排序，重试过的地址排在最后
+pub fn rank(strategy: &dyn Strategy, conn: &ConnectTestResult, download: &mut DownloadTestResult) {
+    let list = match &mut download.list {
+        Some(list) => list,
+        None => return,
+    };
+
+    // 连接或下载时重试过的地址排在没有重试的地址之后，是否重试过和候选数据一起计算
+    let (candidates, flaky): (Vec<Candidate>, Vec<bool>) = list
+        .iter()
+        .map(|x| {
+            let stats = conn.get(x.addr());
+            let candidate = Candidate {
+                latency: stats.map(|x| x.cost.as_secs_f64() * 1000.0),
+                loss: stats.map(|x| x.loss).unwrap_or_default(),
+                jitter: stats
+                    .map(|x| x.jitter.as_secs_f64() * 1000.0)
+                    .unwrap_or_default(),
+                speed: x.speed.byte_value(),
+            };
+            let retries = x.retries + stats.map(|x| x.retries).unwrap_or_default();
+            (candidate, retries > 0)
+        })
+        .unzip();
+
+    list.iter_mut()
+        .zip(&candidates)
+        .for_each(|(stat, x)| stat.score = Some(strategy.score(x, &candidates)));
+    let mut ranked: Vec<(bool, DownloadTestStats)> =
+        flaky.into_iter().zip(list.drain(..)).collect();
+    ranked.sort_by(|(x_flaky, x), (y_flaky, y)| {
+        x_flaky
+            .cmp(y_flaky)
+            .then(y.score.unwrap().total_cmp(&x.score.unwrap()))
+    });
+    list.extend(ranked.into_iter().map(|(_, x)| x));
+}
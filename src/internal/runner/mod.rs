@@ -0,0 +1,216 @@
+use crate::internal::action::{dns, export, hosts, notify};
+use crate::internal::client::conn::ConnectTestResult;
+use crate::internal::client::download::{DownloadTestResult, Speed, SpeedFormat};
+use crate::internal::client::report::{self, RunReport};
+use crate::internal::client::summary::{self, RunSummary};
+use crate::internal::config::def::Config;
+use crate::internal::network::http::Protocol;
+use crate::internal::rank;
+use crate::internal::source::{adaptive, IpList};
+
+use std::error::Error;
+use std::net::SocketAddr;
+use std::time::Instant;
+
+// 测速流程的阶段
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Phase {
+    Conn,
+    Download,
+    Upload,
+}
+
+impl Phase {
+    pub fn parse(s: &str) -> Result<Self, Box<dyn Error>> {
+        match s {
+            "conn" => Ok(Self::Conn),
+            "download" => Ok(Self::Download),
+            "upload" => Ok(Self::Upload),
+            others => Err(format!("invalid phase: {others}").into()),
+        }
+    }
+}
+
+// 配置中的阶段，upload.enable 为 true 时总是包含上传测试
+pub fn phases(conf: &Config) -> Vec<Phase> {
+    let mut phases: Vec<Phase> = conf
+        .phases
+        .iter()
+        .map(|x| Phase::parse(x).unwrap_or_else(|e| panic!("{e}")))
+        .collect();
+    if conf.upload.enable && !phases.contains(&Phase::Upload) {
+        phases.push(Phase::Upload);
+    }
+    phases
+}
+
+// 完整执行一次测速：按 phases 执行连通性测试、下载测试和上传测试，记录历史，然后执行已启用的后续动作
+// 跳过连通性测试时直接对来源中的全部地址测试下载，跳过下载测试时按连通性测试的结果排序
+pub fn run(conf: &Config, ips: &IpList) -> RunReport {
+    let start = Instant::now();
+    let phases = phases(conf);
+
+    let mut adaptive_ips = None;
+    let result = match phases.contains(&Phase::Conn) {
+        true => {
+            let connector = conf.create_conn_test_client(ips.ips.clone());
+            let mut result = connector.connect_test();
+            if conf.conn.adaptive.enable {
+                adaptive_ips = Some(adaptive_scan(conf, ips, &mut result));
+            }
+            if conf.http_options().protocol == Protocol::Http3 {
+                println!("对连接成功的地址进行 QUIC 探测");
+                let quic = conf.create_quic_test_client(result.top_addrs());
+                result.set_quic(&quic.connect_test());
+            }
+            result.apply_tags(adaptive_ips.as_ref().unwrap_or(ips));
+            println!("{result}");
+            result
+        }
+        false => ConnectTestResult::empty(conf.conn.top),
+    };
+    // 包含第二阶段地址的列表，之后的标签都从这里获取
+    let ips = adaptive_ips.as_ref().unwrap_or(ips);
+
+    let downloaded = phases.contains(&Phase::Download);
+    let mut download_result = match downloaded {
+        true => {
+            let candidates = match phases.contains(&Phase::Conn) {
+                true => result.top_addrs(),
+                false => conf.socket_addrs(ips.ips.clone()),
+            };
+            let downloader = conf.create_download_test_client(candidates);
+            downloader.download_test()
+        }
+        false => DownloadTestResult::from_conn(&result, conf.speed_format()),
+    };
+    download_result.apply_tags(ips);
+    let strategy = rank::create_strategy(&conf.rank).unwrap_or_else(|e| panic!("{e}"));
+    rank::rank(strategy.as_ref(), &result, &mut download_result);
+    println!("按 {} 排序", strategy.get_name());
+    if downloaded {
+        println!("{download_result}");
+    }
+
+    // 对排名靠前的地址测试上传速度
+    let upload_result = match phases.contains(&Phase::Upload) {
+        true => {
+            let uploader =
+                conf.create_upload_test_client(download_result.top_addrs(conf.upload.top));
+            let mut upload_result = uploader.upload_test();
+            upload_result.apply_tags(ips);
+            println!("{upload_result}");
+            Some(upload_result)
+        }
+        false => None,
+    };
+
+    let mut run_summary = RunSummary::new(&result, &download_result, conf.speed_format());
+    if !downloaded {
+        run_summary.speed = None;
+    }
+    if !conf.history.path.is_empty() {
+        if let Some(prev) = summary::load_history(&conf.history.path).last() {
+            run_summary.compare(prev);
+        }
+        if let Err(e) = summary::append_history(&conf.history.path, &run_summary) {
+            println!("写入测速历史失败: {e}");
+        }
+    }
+
+    if conf.hosts.enable {
+        if let Err(e) = hosts::update(conf, &download_result) {
+            println!("更新 hosts 文件失败: {e}");
+        }
+    }
+
+    if conf.dns.enable {
+        if let Err(e) = dns::update(conf, &download_result) {
+            println!("更新 DNS 记录失败: {e}");
+        }
+    }
+
+    if conf.export.enable {
+        if let Err(e) = export::export(conf, &download_result) {
+            println!("导出代理配置失败: {e}");
+        }
+    }
+
+    notify::notify(conf, &run_summary);
+    let mut report = RunReport::new(&result, &download_result, run_summary);
+    if !downloaded {
+        report.download.clear();
+    }
+    if let Some(upload_result) = &upload_result {
+        report.set_upload(upload_result);
+    }
+    report.duration = start.elapsed().as_secs_f64();
+
+    if !ips.previous.is_empty() {
+        report.compare(&ips.previous);
+        print_comparison(&report, conf.speed_format());
+    }
+    if !conf.result.path.is_empty() {
+        if let Err(e) = report::write_result(&conf.result.path, &report) {
+            println!("写入测速结果失败: {e}");
+        }
+    }
+    report
+}
+
+// 两阶段扫描：按第一阶段的结果选出最好的几块地址，对块中其余地址再测试一次，结果合并到 result 中
+// 返回包含两个阶段全部地址的列表
+fn adaptive_scan(conf: &Config, ips: &IpList, result: &mut ConnectTestResult) -> IpList {
+    let blocks = adaptive::rank_blocks(&ips.ips, result);
+    println!(
+        "第一阶段共有 {} 块地址可以连接，选择以下地址块进行第二阶段测试：",
+        blocks.len()
+    );
+    blocks.iter().take(conf.conn.adaptive.blocks).for_each(|x| {
+        println!(
+            "{:<18} 耗时 {:?} 成功 {:.0}%",
+            x.net.to_string(),
+            x.latency,
+            x.success * 100.0
+        )
+    });
+
+    let mut all = ips.clone();
+    let mut next = adaptive::plan(ips, result, &conf.conn.adaptive);
+    // 第二阶段的地址来自整块网段，同样需要排除
+    conf.apply_exclude(&mut next);
+    if next.ips.is_empty() {
+        return all;
+    }
+    println!("第二阶段测试 {} 个 ip", next.ips.len());
+    let connector = conf.create_conn_test_client(next.ips.clone());
+    result.merge(connector.connect_test());
+    for ip in &next.ips {
+        next.tags_of(ip).iter().for_each(|tag| all.push(*ip, tag));
+    }
+    all
+}
+
+fn print_comparison(report: &RunReport, format: SpeedFormat) {
+    let latency = |x: Option<f64>| x.map(|x| format!("{x:.0}ms")).unwrap_or("-".to_string());
+    let speed = |x: Option<f64>| {
+        x.map(|x| Speed::from_bytes(x).format(format))
+            .unwrap_or("-".to_string())
+    };
+    println!("与上次结果对比（上次 -> 本次）：");
+    for x in &report.comparison {
+        let note = match (x.old_latency.or(x.old_speed), x.new_latency.or(x.new_speed)) {
+            (Some(_), None) => " (本次失败)",
+            (None, Some(_)) => " (新地址)",
+            _ => "",
+        };
+        println!(
+            "{:<21} 延迟 {:>6} -> {:<6} 速度 {:>12} -> {}{note}",
+            SocketAddr::new(x.ip, x.port).to_string(),
+            latency(x.old_latency),
+            latency(x.new_latency),
+            speed(x.old_speed),
+            speed(x.new_speed),
+        );
+    }
+}
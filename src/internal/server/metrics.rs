@@ -0,0 +1,222 @@
+use crate::internal::client::report::RunReport;
+
+use std::collections::{HashMap, VecDeque};
+use std::fmt::Write;
+use std::net::SocketAddr;
+
+// 每个地址保留的最近测速次数，延迟分位数和丢包率按这些数据计算
+pub const METRICS_WINDOW: usize = 20;
+
+// 连续这么多次测速都没有出现的地址不再导出，避免标签数量无限增长
+pub const METRICS_EXPIRE: u64 = 5;
+
+// 测速耗时直方图的分桶（秒）
+const SCAN_DURATION_BUCKETS: [f64; 8] = [5.0, 10.0, 30.0, 60.0, 120.0, 300.0, 600.0, 1800.0];
+
+#[derive(Default)]
+struct IpMetrics {
+    // 连接耗时（秒），None 表示连接失败
+    samples: VecDeque<Option<f64>>,
+    // 全部成功连接的耗时之和与次数，只增不减，不受 METRICS_WINDOW 限制
+    latency_sum: f64,
+    latency_count: u64,
+    // 最近一次的下载速度（字节/秒）
+    speed: Option<f64>,
+    colo: Option<String>,
+    // 最近一次出现在第几次测速中
+    seen: u64,
+}
+
+impl IpMetrics {
+    fn push(&mut self, sample: Option<f64>) {
+        if self.samples.len() >= METRICS_WINDOW {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(sample);
+        if let Some(x) = sample {
+            self.latency_sum += x;
+            self.latency_count += 1;
+        }
+    }
+
+    fn quantile(&self, q: f64) -> Option<f64> {
+        let mut ok: Vec<f64> = self.samples.iter().flatten().cloned().collect();
+        if ok.is_empty() {
+            return None;
+        }
+        ok.sort_by(|x, y| x.total_cmp(y));
+        let i = ((ok.len() - 1) as f64 * q).round() as usize;
+        Some(ok[i])
+    }
+
+    fn loss(&self) -> f64 {
+        let failed = self.samples.iter().filter(|x| x.is_none()).count();
+        failed as f64 / self.samples.len().max(1) as f64
+    }
+}
+
+// 按 Prometheus 文本格式转义标签值中的 \、" 和换行
+fn escape_label(x: &str) -> String {
+    x.replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[derive(Default)]
+pub struct Metrics {
+    ips: HashMap<SocketAddr, IpMetrics>,
+    // (阶段, 结果) => 次数
+    probes: HashMap<(&'static str, &'static str), u64>,
+    scan_buckets: [u64; SCAN_DURATION_BUCKETS.len()],
+    scan_sum: f64,
+    scan_count: u64,
+}
+
+impl Metrics {
+    fn entry(&mut self, addr: SocketAddr) -> &mut IpMetrics {
+        let seen = self.scan_count + 1;
+        let ip = self.ips.entry(addr).or_default();
+        ip.seen = seen;
+        ip
+    }
+
+    pub fn observe(&mut self, report: &RunReport) {
+        for x in &report.conn {
+            let ip = self.entry(SocketAddr::new(x.ip, x.port));
+            ip.push(Some(x.latency / 1000.0));
+            if x.colo.is_some() {
+                ip.colo = x.colo.clone();
+            }
+        }
+        for x in &report.conn_failed {
+            self.entry(*x).push(None);
+        }
+        for x in &report.download {
+            let ip = self.entry(SocketAddr::new(x.ip, x.port));
+            ip.speed = Some(x.speed);
+            if x.colo.is_some() {
+                ip.colo = x.colo.clone();
+            }
+        }
+
+        self.count("conn", "success", report.conn.len());
+        self.count("conn", "failure", report.conn_failed.len());
+        self.count("download", "success", report.download.len());
+        self.count("download", "failure", report.download_failed.len());
+
+        for (i, le) in SCAN_DURATION_BUCKETS.iter().enumerate() {
+            if report.duration <= *le {
+                self.scan_buckets[i] += 1;
+            }
+        }
+        self.scan_sum += report.duration;
+        self.scan_count += 1;
+
+        let count = self.scan_count;
+        self.ips.retain(|_, m| count - m.seen < METRICS_EXPIRE);
+    }
+
+    fn count(&mut self, phase: &'static str, outcome: &'static str, n: usize) {
+        *self.probes.entry((phase, outcome)).or_default() += n as u64;
+    }
+
+    // Prometheus 文本格式
+    pub fn render(&self) -> String {
+        let mut ips: Vec<(&SocketAddr, &IpMetrics)> = self.ips.iter().collect();
+        ips.sort_by_key(|(addr, _)| **addr);
+        let labels = |addr: &SocketAddr, m: &IpMetrics| {
+            format!(
+                "ip=\"{}\",port=\"{}\",colo=\"{}\"",
+                addr.ip(),
+                addr.port(),
+                escape_label(m.colo.as_deref().unwrap_or(""))
+            )
+        };
+
+        let mut out = String::new();
+        out.push_str("# HELP cf_proxy_test_latency_seconds 连接耗时分位数\n");
+        out.push_str("# TYPE cf_proxy_test_latency_seconds summary\n");
+        for (ip, m) in &ips {
+            for q in [0.5, 0.95] {
+                if let Some(v) = m.quantile(q) {
+                    let _ = writeln!(
+                        out,
+                        "cf_proxy_test_latency_seconds{{{},quantile=\"{q}\"}} {v}",
+                        labels(ip, m)
+                    );
+                }
+            }
+            let _ = writeln!(
+                out,
+                "cf_proxy_test_latency_seconds_sum{{{}}} {}",
+                labels(ip, m),
+                m.latency_sum
+            );
+            let _ = writeln!(
+                out,
+                "cf_proxy_test_latency_seconds_count{{{}}} {}",
+                labels(ip, m),
+                m.latency_count
+            );
+        }
+
+        out.push_str("# HELP cf_proxy_test_loss_ratio 连接失败比例\n");
+        out.push_str("# TYPE cf_proxy_test_loss_ratio gauge\n");
+        for (ip, m) in &ips {
+            let _ = writeln!(
+                out,
+                "cf_proxy_test_loss_ratio{{{}}} {}",
+                labels(ip, m),
+                m.loss()
+            );
+        }
+
+        out.push_str("# HELP cf_proxy_test_download_bytes_per_second 最近一次下载速度\n");
+        out.push_str("# TYPE cf_proxy_test_download_bytes_per_second gauge\n");
+        for (ip, m) in &ips {
+            if let Some(speed) = m.speed {
+                let _ = writeln!(
+                    out,
+                    "cf_proxy_test_download_bytes_per_second{{{}}} {speed}",
+                    labels(ip, m)
+                );
+            }
+        }
+
+        out.push_str("# HELP cf_proxy_test_probes_total 测试次数\n");
+        out.push_str("# TYPE cf_proxy_test_probes_total counter\n");
+        let mut probes: Vec<_> = self.probes.iter().collect();
+        probes.sort();
+        for ((phase, outcome), n) in probes {
+            let _ = writeln!(
+                out,
+                "cf_proxy_test_probes_total{{phase=\"{phase}\",outcome=\"{outcome}\"}} {n}"
+            );
+        }
+
+        out.push_str("# HELP cf_proxy_test_scan_duration_seconds 整次测速耗时\n");
+        out.push_str("# TYPE cf_proxy_test_scan_duration_seconds histogram\n");
+        for (le, n) in SCAN_DURATION_BUCKETS.iter().zip(self.scan_buckets) {
+            let _ = writeln!(
+                out,
+                "cf_proxy_test_scan_duration_seconds_bucket{{le=\"{le}\"}} {n}"
+            );
+        }
+        let _ = writeln!(
+            out,
+            "cf_proxy_test_scan_duration_seconds_bucket{{le=\"+Inf\"}} {}",
+            self.scan_count
+        );
+        let _ = writeln!(
+            out,
+            "cf_proxy_test_scan_duration_seconds_sum {}",
+            self.scan_sum
+        );
+        let _ = writeln!(
+            out,
+            "cf_proxy_test_scan_duration_seconds_count {}",
+            self.scan_count
+        );
+        out
+    }
+}
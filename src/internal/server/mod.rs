@@ -0,0 +1,183 @@
+pub mod metrics;
+
+use crate::internal::client::report::RunReport;
+use crate::internal::client::summary;
+use crate::internal::config::def::Config;
+use crate::internal::runner;
+use crate::internal::source::IpList;
+
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{header, Body, Method, Request, Response, Server, StatusCode};
+use serde_json::{json, Value};
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::panic::{self, AssertUnwindSafe};
+use std::process;
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread;
+use std::time::Duration;
+
+use metrics::Metrics;
+
+#[derive(Default)]
+pub struct State {
+    pub latest: Option<RunReport>,
+    pub running: bool,
+    pub metrics: Metrics,
+}
+
+#[derive(Clone)]
+pub struct Context {
+    pub conf: Arc<Config>,
+    pub state: Arc<Mutex<State>>,
+    pub trigger: Sender<()>,
+}
+
+// 测速线程 panic 时锁会被标记为 poisoned，状态本身仍然可用
+fn lock(state: &Mutex<State>) -> MutexGuard<'_, State> {
+    state.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+fn json_response(status: StatusCode, value: Value) -> Response<Body> {
+    let mut resp = Response::new(Body::from(value.to_string()));
+    *resp.status_mut() = status;
+    resp.headers_mut().insert(
+        header::CONTENT_TYPE,
+        header::HeaderValue::from_static("application/json"),
+    );
+    resp
+}
+
+fn not_found() -> Response<Body> {
+    json_response(StatusCode::NOT_FOUND, json!({ "error": "not found" }))
+}
+
+pub fn handle(ctx: &Context, req: &Request<Body>) -> Response<Body> {
+    match (req.method(), req.uri().path()) {
+        (&Method::GET, "/healthz") => {
+            let state = lock(&ctx.state);
+            json_response(
+                StatusCode::OK,
+                json!({
+                    "status": "ok",
+                    "running": state.running,
+                    "last_run": state.latest.as_ref().map(|x| x.summary.time),
+                }),
+            )
+        }
+        (&Method::GET, "/results/latest") => match &lock(&ctx.state).latest {
+            Some(report) => json_response(StatusCode::OK, json!(report)),
+            None => not_found(),
+        },
+        (&Method::GET, "/best") => match &lock(&ctx.state).latest {
+            Some(report) if report.summary.best_ip.is_some() => {
+                json_response(StatusCode::OK, json!(report.summary))
+            }
+            _ => not_found(),
+        },
+        (&Method::GET, "/metrics") => {
+            let mut resp = Response::new(Body::from(lock(&ctx.state).metrics.render()));
+            resp.headers_mut().insert(
+                header::CONTENT_TYPE,
+                header::HeaderValue::from_static("text/plain; version=0.0.4"),
+            );
+            resp
+        }
+        (&Method::GET, "/history") => {
+            let history = summary::load_history(&ctx.conf.history.path);
+            json_response(StatusCode::OK, json!(history))
+        }
+        (&Method::POST, "/run") => {
+            let mut state = lock(&ctx.state);
+            if state.running {
+                return json_response(StatusCode::CONFLICT, json!({ "status": "running" }));
+            }
+            state.running = true;
+            match ctx.trigger.send(()) {
+                Ok(()) => json_response(StatusCode::ACCEPTED, json!({ "status": "started" })),
+                Err(_) => {
+                    state.running = false;
+                    json_response(
+                        StatusCode::SERVICE_UNAVAILABLE,
+                        json!({ "error": "scanner stopped" }),
+                    )
+                }
+            }
+        }
+        _ => not_found(),
+    }
+}
+
+// 测速线程：启动时先测一次，之后每隔 interval 或者收到 /run 请求时再测
+fn spawn_worker(
+    conf: Arc<Config>,
+    ips: IpList,
+    state: Arc<Mutex<State>>,
+    trigger: mpsc::Receiver<()>,
+) {
+    let interval = Duration::from_secs(conf.server.interval * 60);
+    thread::spawn(move || loop {
+        lock(&state).running = true;
+        // 测速异常退出时也要清除 running，否则之后的 /run 会一直返回 409
+        let result = panic::catch_unwind(AssertUnwindSafe(|| runner::run(&conf, &ips)));
+        {
+            let mut state = lock(&state);
+            state.running = false;
+            match result {
+                Ok(report) => {
+                    state.metrics.observe(&report);
+                    state.latest = Some(report);
+                }
+                Err(_) => println!("本次测速异常退出，等待下一次测速"),
+            }
+        }
+
+        let next = if interval.is_zero() {
+            trigger.recv().map_err(|_| RecvTimeoutError::Disconnected)
+        } else {
+            trigger.recv_timeout(interval)
+        };
+        if let Err(RecvTimeoutError::Disconnected) = next {
+            break;
+        }
+    });
+}
+
+// 守护模式：启动 HTTP API 并在后台定时测速
+pub fn serve(conf: Config, ips: IpList) {
+    let addr: SocketAddr = match conf.server.listen.parse() {
+        Ok(addr) => addr,
+        Err(e) => {
+            println!("配置错误 server.listen: {} ({e})", conf.server.listen);
+            process::exit(1);
+        }
+    };
+    let conf = Arc::new(conf);
+    let state = Arc::new(Mutex::new(State::default()));
+    let (tx, rx) = mpsc::channel();
+    spawn_worker(conf.clone(), ips, state.clone(), rx);
+
+    let ctx = Context {
+        conf,
+        state,
+        trigger: tx,
+    };
+    let rt = tokio::runtime::Runtime::new().unwrap();
+    rt.block_on(async move {
+        let make = make_service_fn(move |_| {
+            let ctx = ctx.clone();
+            async move {
+                Ok::<_, Infallible>(service_fn(move |req: Request<Body>| {
+                    let resp = handle(&ctx, &req);
+                    async move { Ok::<_, Infallible>(resp) }
+                }))
+            }
+        });
+
+        println!("HTTP API 监听于 http://{addr}");
+        if let Err(e) = Server::bind(&addr).serve(make).await {
+            println!("HTTP API 服务异常退出: {e}");
+        }
+    });
+}
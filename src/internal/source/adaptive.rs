@@ -0,0 +1,90 @@
+use super::cidr::{self, V4_BLOCK_PREFIX, V6_BLOCK_PREFIX};
+use super::IpList;
+use crate::internal::client::conn::ConnectTestResult;
+use crate::internal::config::def::AdaptiveConfig;
+
+use ipnet::IpNet;
+use std::collections::{HashMap, HashSet};
+use std::net::IpAddr;
+use std::time::Duration;
+
+// 第一阶段中一块地址的测试结果
+#[derive(Clone, Debug, PartialEq)]
+pub struct BlockScore {
+    pub net: IpNet,
+    // 块中连接最快的地址的耗时
+    pub latency: Duration,
+    // 块中连接成功的地址比例
+    pub success: f64,
+}
+
+// ip 所在的块，IPv4 为 /24，IPv6 为 /48
+pub fn block_of(ip: IpAddr) -> IpNet {
+    let prefix = match ip {
+        IpAddr::V4(_) => V4_BLOCK_PREFIX,
+        IpAddr::V6(_) => V6_BLOCK_PREFIX,
+    };
+    IpNet::new(ip, prefix).unwrap().trunc()
+}
+
+// 按第一阶段的结果给每块打分，按最快耗时排序，耗时相同时成功比例高的在前，没有成功连接的块不参与排序
+pub fn rank_blocks(probed: &[IpAddr], result: &ConnectTestResult) -> Vec<BlockScore> {
+    let mut total: HashMap<IpNet, usize> = HashMap::new();
+    probed
+        .iter()
+        .for_each(|ip| *total.entry(block_of(*ip)).or_default() += 1);
+
+    let mut best: HashMap<IpNet, Duration> = HashMap::new();
+    let mut succeeded: HashMap<IpNet, HashSet<IpAddr>> = HashMap::new();
+    for x in result.list() {
+        let net = block_of(x.ip);
+        let latency = best.entry(net).or_insert(x.cost);
+        *latency = (*latency).min(x.cost);
+        succeeded.entry(net).or_default().insert(x.ip);
+    }
+
+    let mut blocks: Vec<BlockScore> = best
+        .into_iter()
+        .map(|(net, latency)| BlockScore {
+            net,
+            latency,
+            success: succeeded[&net].len() as f64 / total.get(&net).copied().unwrap_or(1) as f64,
+        })
+        .collect();
+    blocks.sort_by(|x, y| {
+        x.latency
+            .cmp(&y.latency)
+            .then(y.success.total_cmp(&x.success))
+    });
+    blocks
+}
+
+// 第二阶段的地址：排名前 blocks 块中还没有测试过的地址，继承块中第一阶段地址的来源标签
+pub fn plan(list: &IpList, result: &ConnectTestResult, conf: &AdaptiveConfig) -> IpList {
+    let mut tags: HashMap<IpNet, Vec<String>> = HashMap::new();
+    for ip in &list.ips {
+        let block_tags = tags.entry(block_of(*ip)).or_default();
+        for tag in list.tags_of(ip) {
+            if !block_tags.contains(&tag) {
+                block_tags.push(tag);
+            }
+        }
+    }
+
+    let probed: HashSet<&IpAddr> = list.ips.iter().collect();
+    let mut next = IpList::default();
+    let mut blocks = rank_blocks(&list.ips, result);
+    blocks.truncate(conf.blocks);
+    for block in blocks {
+        for ip in cidr::sample_block(block.net.network(), block.net.prefix_len(), conf.dense) {
+            if probed.contains(&ip) {
+                continue;
+            }
+            tags.get(&block.net)
+                .into_iter()
+                .flatten()
+                .for_each(|tag| next.push(ip, tag));
+        }
+    }
+    next
+}
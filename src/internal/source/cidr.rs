@@ -0,0 +1,91 @@
+use ipnet::IpNet;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+// 展开网段时按块取样，IPv4 以 /24 为一块，IPv6 以 /48 为一块
+pub const V4_BLOCK_PREFIX: u8 = 24;
+pub const V6_BLOCK_PREFIX: u8 = 48;
+
+// IPv6 的一块地址太多，取全部地址时最多取的地址数量
+pub const V6_DENSE_LIMIT: usize = 256;
+
+fn to_u128(ip: IpAddr) -> u128 {
+    match ip {
+        IpAddr::V4(ip) => u32::from(ip) as u128,
+        IpAddr::V6(ip) => u128::from(ip),
+    }
+}
+
+fn from_u128(x: u128, v4: bool) -> IpAddr {
+    if v4 {
+        IpAddr::V4(Ipv4Addr::from(x as u32))
+    } else {
+        IpAddr::V6(Ipv6Addr::from(x))
+    }
+}
+
+fn pow2(bits: u8) -> u128 {
+    if bits >= 127 {
+        return u128::MAX;
+    }
+    1u128 << bits
+}
+
+pub fn block_prefix(net: &IpNet) -> u8 {
+    match net {
+        IpNet::V4(_) => V4_BLOCK_PREFIX,
+        IpNet::V6(_) => V6_BLOCK_PREFIX,
+    }
+}
+
+// 在一块地址中均匀地取 count 个地址，count 为 0 时取全部地址（IPv6 最多取 V6_DENSE_LIMIT 个）
+// 跳过块中的第一个地址，IPv4 还跳过最后一个广播地址
+pub fn sample_block(start: IpAddr, prefix: u8, count: usize) -> Vec<IpAddr> {
+    let v4 = start.is_ipv4();
+    let bits = if v4 { 32 } else { 128 };
+    let size = pow2(bits - prefix);
+    let base = to_u128(start) & !(size - 1);
+
+    // 只有一两个地址的块不跳过
+    let (first, last) = match size {
+        0..=2 => (0, size),
+        _ if v4 => (1, size - 1),
+        _ => (1, size),
+    };
+    let usable = last - first;
+    let count = match count {
+        0 if v4 => usable,
+        0 => usable.min(V6_DENSE_LIMIT as u128),
+        count => count as u128,
+    };
+
+    if count >= usable {
+        return (first..last)
+            .map(|offset| from_u128(base + offset, v4))
+            .collect();
+    }
+
+    let step = usable / count;
+    (0..count)
+        .map(|i| from_u128(base + first + i * step, v4))
+        .collect()
+}
+
+// 把网段展开为地址列表：最多取 max_blocks 块（均匀分布），每块取 per_block 个地址
+pub fn expand(net: IpNet, per_block: usize, max_blocks: usize) -> Vec<IpAddr> {
+    let net = net.trunc();
+    let block = block_prefix(&net).max(net.prefix_len());
+    let bits = net.max_prefix_len();
+    let blocks = pow2(block - net.prefix_len());
+    let block_size = pow2(bits - block);
+    let step = (blocks / max_blocks.max(1) as u128).max(1);
+
+    let base = to_u128(net.network());
+    let mut ips = Vec::new();
+    let mut i = 0;
+    while i < blocks && (i / step) < max_blocks.max(1) as u128 {
+        let start = from_u128(base + i * block_size, net.network().is_ipv4());
+        ips.extend(sample_block(start, block, per_block));
+        i = i.saturating_add(step);
+    }
+    ips
+}
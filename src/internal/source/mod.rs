@@ -0,0 +1,232 @@
+pub mod adaptive;
+pub mod cidr;
+pub mod remote;
+pub mod result;
+
+use crate::internal::client::report::ResultRow;
+use crate::internal::config::def::{CidrConfig, FetchConfig};
+
+use ipnet::IpNet;
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, BufRead, BufReader, Read};
+use std::net::{IpAddr, SocketAddr};
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+// 从标准输入读取 ip 的来源
+pub const STDIN_SOURCE: &str = "-";
+
+// 一个 ip 来源，可以是文件、目录、标准输入或 http(s) 地址
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Source {
+    pub path: String,
+    // 来源标签，为空时使用 path
+    #[serde(default)]
+    pub tag: String,
+    // 来源为上次的结果文件（.json/.csv）时，按 by 排序后只取前 top 个 ip，为 0 时取全部
+    #[serde(default)]
+    pub top: usize,
+    // 排序的列：speed, score, latency，为空时保持文件中的顺序
+    #[serde(default)]
+    pub by: String,
+}
+
+impl Source {
+    pub fn new(path: &str) -> Self {
+        Self {
+            path: path.to_string(),
+            tag: String::new(),
+            top: 0,
+            by: String::new(),
+        }
+    }
+
+    pub fn tag(&self) -> &str {
+        if self.tag.is_empty() {
+            return self.path.as_str();
+        }
+        self.tag.as_str()
+    }
+}
+
+// 合并去重后的 ip 列表，以及每个 ip 所属的来源标签
+#[derive(Clone, Debug, Default)]
+pub struct IpList {
+    pub ips: Vec<IpAddr>,
+    pub tags: HashMap<IpAddr, Vec<String>>,
+    // 来自结果文件的地址上次的测速数据，用于对比
+    pub previous: HashMap<SocketAddr, ResultRow>,
+}
+
+impl IpList {
+    // 把 previous 中没有端口（端口为 0）的地址改为 port
+    pub fn set_default_port(&mut self, port: u16) {
+        self.previous = std::mem::take(&mut self.previous)
+            .into_values()
+            .map(|mut x| {
+                if x.port == 0 {
+                    x.port = port;
+                }
+                (x.addr(), x)
+            })
+            .collect();
+    }
+
+    pub fn push(&mut self, ip: IpAddr, tag: &str) {
+        match self.tags.get_mut(&ip) {
+            Some(tags) => {
+                if !tags.iter().any(|x| x == tag) {
+                    tags.push(tag.to_string());
+                }
+            }
+            None => {
+                self.ips.push(ip);
+                self.tags.insert(ip, vec![tag.to_string()]);
+            }
+        }
+    }
+
+    pub fn tags_of(&self, ip: &IpAddr) -> Vec<String> {
+        self.tags.get(ip).cloned().unwrap_or_default()
+    }
+
+    // 移除属于 nets 中任一网段的 ip，返回移除的数量
+    pub fn exclude(&mut self, nets: &[IpNet]) -> usize {
+        let before = self.ips.len();
+        let tags = &mut self.tags;
+        self.ips.retain(|ip| {
+            if nets.iter().any(|net| net.contains(ip)) {
+                tags.remove(ip);
+                return false;
+            }
+            true
+        });
+        before - self.ips.len()
+    }
+}
+
+// 解析单个 ip 或网段，单个 ip 视为 /32 或 /128
+pub fn parse_net(s: &str) -> Option<IpNet> {
+    let s = s.trim();
+    match s.parse::<IpAddr>() {
+        Ok(ip) => Some(IpNet::from(ip)),
+        Err(_) => s.parse().ok(),
+    }
+}
+
+// 逐行解析 ip 或网段，跳过空行和 # 开头的注释行
+pub fn parse_nets(content: &str, name: &str) -> Vec<IpNet> {
+    let mut nets = Vec::new();
+    for line in content.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        match parse_net(line) {
+            Some(net) => nets.push(net),
+            None => println!("忽略 {name} 中无效的 ip 或网段: {line}"),
+        }
+    }
+    nets
+}
+
+// 逐行解析 ip 或网段，跳过空行和 # 开头的注释行，网段按 cidr 配置展开
+pub fn parse_ips(content: &str, name: &str, cidr: &CidrConfig) -> Vec<IpAddr> {
+    let mut ips = Vec::new();
+    for line in content.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        if let Ok(ip) = line.parse() {
+            ips.push(ip);
+            continue;
+        }
+        match line.parse::<IpNet>() {
+            Ok(net) => ips.extend(cidr::expand(net, cidr.per_block, cidr.max_blocks)),
+            Err(_) => println!("忽略 {name} 中无效的 ip: {line}"),
+        }
+    }
+    ips
+}
+
+fn read_file(path: &Path) -> io::Result<String> {
+    let mut content = String::new();
+    BufReader::new(fs::File::open(path)?).read_to_string(&mut content)?;
+    Ok(content)
+}
+
+// 读取一个来源中的全部内容，目录按文件名顺序读取其中的所有文件
+fn read_source(
+    source: &Source,
+    fetch: &FetchConfig,
+) -> Result<Vec<(String, String)>, Box<dyn std::error::Error>> {
+    if remote::is_remote(&source.path) {
+        return Ok(vec![(
+            source.path.clone(),
+            remote::fetch(&source.path, fetch)?,
+        )]);
+    }
+
+    if source.path == STDIN_SOURCE {
+        let mut content = String::new();
+        for line in io::stdin().lock().lines() {
+            content.push_str(&line?);
+            content.push('\n');
+        }
+        return Ok(vec![("stdin".to_string(), content)]);
+    }
+
+    let path = Path::new(&source.path);
+    if !path.is_dir() {
+        return Ok(vec![(source.path.clone(), read_file(path)?)]);
+    }
+
+    let mut files: Vec<_> = fs::read_dir(path)?
+        .filter_map(|x| x.ok())
+        .map(|x| x.path())
+        .filter(|x| x.is_file())
+        .collect();
+    files.sort();
+
+    let mut contents = Vec::new();
+    for file in files {
+        contents.push((file.display().to_string(), read_file(&file)?));
+    }
+    Ok(contents)
+}
+
+// 读取所有来源并合并去重
+pub fn load(sources: &[Source], fetch: &FetchConfig, cidr: &CidrConfig) -> IpList {
+    let mut list = IpList::default();
+    for source in sources {
+        println!("从 {} 加载 ip 数据 ...", source.path);
+        let contents = match read_source(source, fetch) {
+            Ok(contents) => contents,
+            Err(e) => {
+                println!("读取 {} 失败: {e}", source.path);
+                continue;
+            }
+        };
+
+        for (name, content) in contents {
+            if result::is_result(&name) {
+                match result::parse(&name, &content, source) {
+                    Ok(rows) => rows.into_iter().for_each(|x| {
+                        list.push(x.ip, source.tag());
+                        list.previous.entry(x.addr()).or_insert(x);
+                    }),
+                    Err(e) => println!("解析结果文件 {name} 失败: {e}"),
+                }
+                continue;
+            }
+            parse_ips(&content, &name, cidr)
+                .into_iter()
+                .for_each(|ip| list.push(ip, source.tag()));
+        }
+    }
+    println!("共加载 {} 个 ip", list.ips.len());
+    list
+}
@@ -0,0 +1,110 @@
+use crate::internal::config::def::FetchConfig;
+
+use reqwest::header::{ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED};
+use reqwest::StatusCode;
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::fs;
+use std::path::PathBuf;
+use std::time::Duration;
+
+// 缓存文件的元数据，用于下次请求时验证缓存
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct CacheMeta {
+    url: String,
+    etag: Option<String>,
+    last_modified: Option<String>,
+}
+
+pub fn is_remote(path: &str) -> bool {
+    path.starts_with("http://") || path.starts_with("https://")
+}
+
+// FNV-1a，用于生成稳定的缓存文件名
+fn cache_key(url: &str) -> String {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for b in url.as_bytes() {
+        hash ^= *b as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    format!("{hash:016x}")
+}
+
+fn cache_paths(url: &str, conf: &FetchConfig) -> (PathBuf, PathBuf) {
+    let dir = PathBuf::from(&conf.cache_dir);
+    let key = cache_key(url);
+    (
+        dir.join(format!("{key}.txt")),
+        dir.join(format!("{key}.meta.json")),
+    )
+}
+
+async fn download(
+    url: &str,
+    meta: &CacheMeta,
+    timeout: Duration,
+) -> Result<Option<(String, CacheMeta)>, Box<dyn Error>> {
+    let client = reqwest::Client::builder().timeout(timeout).build()?;
+    let mut req = client.get(url);
+    if let Some(etag) = &meta.etag {
+        req = req.header(IF_NONE_MATCH, etag);
+    }
+    if let Some(last_modified) = &meta.last_modified {
+        req = req.header(IF_MODIFIED_SINCE, last_modified);
+    }
+
+    let resp = req.send().await?;
+    if resp.status() == StatusCode::NOT_MODIFIED {
+        return Ok(None);
+    }
+    if !resp.status().is_success() {
+        return Err(format!("status {}", resp.status()).into());
+    }
+
+    let header = |name| {
+        resp.headers()
+            .get(name)
+            .and_then(|x: &reqwest::header::HeaderValue| x.to_str().ok())
+            .map(|x| x.to_string())
+    };
+    let new_meta = CacheMeta {
+        url: url.to_string(),
+        etag: header(ETAG),
+        last_modified: header(LAST_MODIFIED),
+    };
+    Ok(Some((resp.text().await?, new_meta)))
+}
+
+// 下载远程 ip 列表，缓存未过期时使用缓存，下载失败时退回到缓存
+pub fn fetch(url: &str, conf: &FetchConfig) -> Result<String, Box<dyn Error>> {
+    let (data_path, meta_path) = cache_paths(url, conf);
+    let cached = fs::read_to_string(&data_path).ok();
+    let meta: CacheMeta = match (&cached, fs::read_to_string(&meta_path)) {
+        (Some(_), Ok(x)) => serde_json::from_str(&x).unwrap_or_default(),
+        _ => CacheMeta::default(),
+    };
+
+    let rt = tokio::runtime::Runtime::new()?;
+    match rt.block_on(download(url, &meta, Duration::from_secs(conf.timeout))) {
+        Ok(Some((content, meta))) => {
+            if let Err(e) = fs::create_dir_all(&conf.cache_dir)
+                .and_then(|_| fs::write(&data_path, &content))
+                .and_then(|_| fs::write(&meta_path, serde_json::to_string(&meta)?))
+            {
+                println!("写入 {url} 的缓存失败: {e}");
+            }
+            Ok(content)
+        }
+        Ok(None) => {
+            println!("{url} 未变化，使用缓存");
+            cached.ok_or("cache not found".into())
+        }
+        Err(e) => match cached {
+            Some(content) => {
+                println!("下载 {url} 失败: {e}，使用缓存");
+                Ok(content)
+            }
+            None => Err(e),
+        },
+    }
+}
@@ -0,0 +1,47 @@
+use super::Source;
+use crate::internal::client::report::{self, ResultRow};
+
+use std::cmp::Ordering;
+use std::collections::HashSet;
+use std::error::Error;
+
+// 以 .json 或 .csv 结尾的来源按上次的结果文件处理
+pub fn is_result(name: &str) -> bool {
+    let name = name.to_lowercase();
+    name.ends_with(".json") || name.ends_with(".csv")
+}
+
+// 按 by 排序，缺少该列数据的地址排在最后
+fn sort(rows: &mut [ResultRow], by: &str) -> Result<(), Box<dyn Error>> {
+    let key: fn(&ResultRow) -> Option<f64> = match by {
+        "" => return Ok(()),
+        "speed" => |x| x.speed,
+        "score" => |x| x.score,
+        "latency" => |x| x.latency.map(|x| -x),
+        others => return Err(format!("invalid column: {others}").into()),
+    };
+    rows.sort_by(|x, y| match (key(x), key(y)) {
+        (Some(x), Some(y)) => y.total_cmp(&x),
+        (Some(_), None) => Ordering::Less,
+        (None, Some(_)) => Ordering::Greater,
+        (None, None) => Ordering::Equal,
+    });
+    Ok(())
+}
+
+// 解析结果文件，按 source 的 by 排序后取前 top 个 ip 的全部端口，同一个地址只保留排名最靠前的一行
+pub fn parse(name: &str, content: &str, source: &Source) -> Result<Vec<ResultRow>, Box<dyn Error>> {
+    let mut rows = report::parse_result(name, content)?;
+    sort(&mut rows, &source.by)?;
+
+    let mut ips = HashSet::new();
+    let mut addrs = HashSet::new();
+    rows.retain(|x| {
+        if !ips.contains(&x.ip) && source.top > 0 && ips.len() >= source.top {
+            return false;
+        }
+        ips.insert(x.ip);
+        addrs.insert(x.addr())
+    });
+    Ok(rows)
+}
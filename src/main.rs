@@ -4,16 +4,104 @@ mod internal;
 mod test;
 
 use internal::client;
+use internal::client::args::Action;
+use internal::client::summary;
 use internal::config::def::Config;
+use internal::source::Source;
+use internal::{runner, server};
+
+use std::process;
 
 fn main() {
     let args = client::args::Command::init();
-    let (conf, ips) = Config::init(&args.conf_path, &args.ip_src);
-    let connector = conf.create_conn_test_client(ips);
-    let result = connector.connect_test();
-    println!("{result}");
-    let conn_top = result.top_ips();
-    let downloader = conf.create_download_test_client(conn_top);
-    let download_result = downloader.download_test();
-    println!("{download_result}")
+    match args.action {
+        Action::ConfigInit(force) => init_config(&args.conf_path, force),
+        Action::ConfigCheck => check_config(&args.conf_path),
+        Action::History(limit) => show_history(&args.conf_path, limit),
+        _ => run(args),
+    }
+}
+
+fn run(args: client::args::Command) {
+    let sources = args
+        .ip_src
+        .iter()
+        .map(|x| Source {
+            top: args.src_top,
+            by: args.src_by.clone(),
+            ..Source::new(x)
+        })
+        .collect();
+    let (mut conf, ips) = Config::init(&args.conf_path, sources, args.phases);
+    if let Some(method) = args.method {
+        conf.conn.method = method;
+    }
+
+    match args.action {
+        Action::Daemon => server::serve(conf, ips),
+        Action::Export => {
+            conf.export.enable = true;
+            runner::run(&conf, &ips);
+        }
+        _ => {
+            runner::run(&conf, &ips);
+        }
+    }
+}
+
+fn init_config(path: &str, force: bool) {
+    match Config::write_default(path, force) {
+        Ok(()) => println!("已生成配置文件 {path}"),
+        Err(e) => {
+            println!("生成配置文件失败: {e}");
+            process::exit(1);
+        }
+    }
+}
+
+fn check_config(path: &str) {
+    let errors = match Config::load(path) {
+        Ok(conf) => conf.validate(),
+        Err(e) => vec![e.to_string()],
+    };
+    if errors.is_empty() {
+        println!("配置文件 {path} 有效");
+        return;
+    }
+    println!("配置文件 {path} 有 {} 个错误：", errors.len());
+    errors.iter().for_each(|x| println!("  {x}"));
+    process::exit(1);
+}
+
+fn show_history(path: &str, limit: usize) {
+    let conf = match Config::load(path) {
+        Ok(conf) => conf,
+        Err(e) => {
+            println!("加载配置文件 {path} 失败: {e}");
+            process::exit(1);
+        }
+    };
+    if conf.history.path.is_empty() {
+        println!("没有配置 history.path，没有测速历史");
+        return;
+    }
+
+    let mut history = summary::load_history(&conf.history.path);
+    history.iter_mut().for_each(|x| x.format = conf.speed_format());
+    println!("最近 {} 次测速：", limit.min(history.len()));
+    for x in &history[history.len().saturating_sub(limit)..] {
+        let addr = match (x.best_ip, x.best_port) {
+            (Some(ip), Some(port)) => std::net::SocketAddr::new(ip, port).to_string(),
+            (Some(ip), None) => ip.to_string(),
+            _ => "-".to_string(),
+        };
+        println!(
+            "{} {:<21} 数据中心 {:<4} 延迟 {:<6} 下载速度 {}",
+            x.time,
+            addr,
+            x.colo.as_deref().unwrap_or("-"),
+            x.latency_text(),
+            x.speed_text()
+        );
+    }
 }
@@ -1,19 +1,1707 @@
+use crate::internal::action::dns::{self, cloudflare::CloudflareProvider};
+use crate::internal::action::export::{self, Format};
+use crate::internal::action::hosts;
+use crate::internal::action::notify::{self, smtp, smtp::SmtpNotifier, webhook::WebhookNotifier};
+use crate::internal::client::args::{self, Action, Command};
+use crate::internal::client::download::{
+    DownloadTestResult, DownloadTestStats, Speed, SpeedFormat, SpeedPrefix, SpeedSeries, SpeedUnit,
+};
+use crate::internal::client::report::{ConnReportEntry, RunReport};
+use crate::internal::client::retry::ErrorKind;
+use crate::internal::client::summary::RunSummary;
 use crate::internal::config::def::Config;
+use crate::internal::config::def::{
+    AdaptiveConfig, CidrConfig, FetchConfig, RankWeights, SmtpConfig, WebhookConfig,
+};
+use crate::internal::rank::{self, Candidate, LexicographicStrategy, Strategy};
+use crate::internal::runner::{self, Phase};
+use crate::internal::server::{self, metrics, metrics::Metrics};
+use crate::internal::source::{self, adaptive, cidr, remote, IpList, Source};
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Request, Response, Server};
+use std::convert::Infallible;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{mpsc, Arc, Mutex};
 use std::{path::PathBuf, time::Duration};
 
+type RequestLog = Arc<Mutex<Vec<String>>>;
+
+// 在后台线程启动一个本地 HTTP 服务，handler 根据 "METHOD path?query" 和请求体返回响应体
+fn mock_server(handler: fn(&str, &str) -> String) -> (SocketAddr, RequestLog) {
+    mock_http_server(move |_, line, body| Response::new(Body::from(handler(line, body))))
+}
+
+// 与 mock_server 相同，handler 可以读取请求头并返回完整的响应
+fn mock_http_server<F>(handler: F) -> (SocketAddr, RequestLog)
+where
+    F: Fn(&hyper::HeaderMap, &str, &str) -> Response<Body> + Send + Sync + 'static,
+{
+    let log: RequestLog = Arc::new(Mutex::new(Vec::new()));
+    let handler = Arc::new(handler);
+    let (tx, rx) = mpsc::channel();
+    let server_log = log.clone();
+    std::thread::spawn(move || {
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        rt.block_on(async move {
+            let make = make_service_fn(move |_| {
+                let log = server_log.clone();
+                let handler = handler.clone();
+                async move {
+                    Ok::<_, Infallible>(service_fn(move |req: Request<Body>| {
+                        let log = log.clone();
+                        let handler = handler.clone();
+                        async move {
+                            let line = format!("{} {}", req.method(), req.uri());
+                            let headers = req.headers().clone();
+                            let body = hyper::body::to_bytes(req.into_body()).await.unwrap();
+                            let body = String::from_utf8_lossy(&body).to_string();
+                            let resp = handler(&headers, &line, &body);
+                            log.lock().unwrap().push(format!("{line} {body}"));
+                            Ok::<_, Infallible>(resp)
+                        }
+                    }))
+                }
+            });
+            let server = Server::bind(&"127.0.0.1:0".parse().unwrap()).serve(make);
+            tx.send(server.local_addr()).unwrap();
+            server.await.unwrap();
+        });
+    });
+    (rx.recv().unwrap(), log)
+}
+
+// 读取示例配置和示例 ip 文件
+fn example_config() -> Config {
+    let path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    let mut conf_path = path.clone();
+    conf_path.push("src/config/example.yaml");
+    let mut ip_path = path.clone();
+    ip_path.push("src/config/example.ip.txt");
+    let (conf, _) = Config::init(
+        conf_path.to_str().unwrap(),
+        vec![Source::new(ip_path.to_str().unwrap())],
+        None,
+    );
+    conf
+}
+
 #[test]
 fn test_read_config() {
     let path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
 
     // 读配置
     let mut conf_path = path.clone();
-    conf_path.push("src/config/conf.yaml");
+    conf_path.push("src/config/example.yaml");
     // 读 ip 文件
     let mut ip_path = path.clone();
-    ip_path.push("src/config/ip.txt");
+    ip_path.push("src/config/example.ip.txt");
 
     // let mut conf: Config = Config::new(conf_path.to_str().unwrap());
-    let (conf, ips) = Config::init(conf_path.to_str().unwrap(), ip_path.to_str().unwrap());
+    let (conf, ips) = Config::init(
+        conf_path.to_str().unwrap(),
+        vec![Source::new(ip_path.to_str().unwrap())],
+        None,
+    );
+    assert!(!ips.ips.is_empty());
 
     let timeout = Duration::from_secs(conf.conn.timeout);
+    assert_eq!(timeout, Duration::from_secs(10));
+}
+
+#[test]
+fn test_hosts_update() {
+    let mut conf = example_config();
+
+    let mut hosts_path = std::env::temp_dir();
+    hosts_path.push(format!("cf-proxy-test-hosts-{}", std::process::id()));
+    std::fs::write(&hosts_path, "127.0.0.1\tlocalhost\n").unwrap();
+    conf.hosts.path = hosts_path.to_str().unwrap().to_string();
+    conf.hosts.names = vec!["www.xiu2.xyz".to_string()];
+
+    let result = DownloadTestResult {
+        top: 1,
+        list: Some(vec![DownloadTestStats::new(
+            "1.1.1.1:443".parse().unwrap(),
+            Speed::byte_per_second(1024, Duration::from_secs(1)),
+        )]),
+        failed: Vec::new(),
+    };
+
+    hosts::update(&conf, &result).unwrap();
+    // 再次写入时替换原有区块，而不是重复追加
+    hosts::update(&conf, &result).unwrap();
+
+    let content = std::fs::read_to_string(&hosts_path).unwrap();
+    assert_eq!(
+        content,
+        format!(
+            "127.0.0.1\tlocalhost\n{}\n1.1.1.1\tcf.xiu2.xyz\n1.1.1.1\twww.xiu2.xyz\n{}\n",
+            hosts::BLOCK_BEGIN,
+            hosts::BLOCK_END
+        )
+    );
+    let backup = std::fs::read_to_string(format!("{}.bak", conf.hosts.path)).unwrap();
+    assert_eq!(backup, "127.0.0.1\tlocalhost\n");
+
+    std::fs::remove_file(&hosts_path).unwrap();
+    std::fs::remove_file(format!("{}.bak", conf.hosts.path)).unwrap();
+}
+
+#[test]
+fn test_hosts_unterminated_block() {
+    let names = vec!["cf.xiu2.xyz".to_string()];
+    let content = format!(
+        "127.0.0.1\tlocalhost\n{}\n1.0.0.1\tcf.xiu2.xyz\n::1\tlocalhost\n",
+        hosts::BLOCK_BEGIN
+    );
+    // 缺少结束标记时不能删除标记之后的内容
+    assert!(hosts::render(&content, "1.1.1.1".parse().unwrap(), &names).is_err());
+
+    let output = hosts::render("::1\tlocalhost", "1.1.1.1".parse().unwrap(), &names).unwrap();
+    assert_eq!(
+        output,
+        format!(
+            "::1\tlocalhost\n{}\n1.1.1.1\tcf.xiu2.xyz\n{}\n",
+            hosts::BLOCK_BEGIN,
+            hosts::BLOCK_END
+        )
+    );
+}
+
+#[test]
+fn test_dns_cloudflare_update() {
+    let (addr, log) = mock_server(|line, _| {
+        if line.starts_with("GET") {
+            return r#"{"success":true,"errors":[],"result":[{"id":"r1","content":"1.1.1.1"},{"id":"r2","content":"9.9.9.9"}]}"#.to_string();
+        }
+        r#"{"success":true,"errors":[],"result":{}}"#.to_string()
+    });
+
+    let provider = CloudflareProvider::build(
+        format!("http://{addr}").as_str(),
+        "token",
+        "zone",
+        Duration::from_secs(5),
+    );
+    let ips = vec!["1.1.1.1".parse().unwrap(), "1.0.0.1".parse().unwrap()];
+    tokio::runtime::Runtime::new()
+        .unwrap()
+        .block_on(dns::update_records(
+            &provider,
+            &["cf.example.com".to_string()],
+            &ips,
+            60,
+        ))
+        .unwrap();
+
+    let log = log.lock().unwrap();
+    assert_eq!(log.len(), 3);
+    assert!(log[0].starts_with("GET /zones/zone/dns_records?type=A&name=cf.example.com"));
+    // 先创建新记录，再删除旧记录
+    assert!(log[1].starts_with("POST /zones/zone/dns_records"));
+    assert!(log[1].contains(r#""content":"1.0.0.1""#));
+    assert!(log[2].starts_with("DELETE /zones/zone/dns_records/r2"));
+}
+
+#[test]
+fn test_dns_rfc2136_message() {
+    let ips = vec!["1.1.1.1".parse().unwrap()];
+    let msg = dns::rfc2136::build_message(7, "example.com", "cf.example.com", &ips, 60).unwrap();
+
+    // id 7，opcode UPDATE，1 个 zone，2 条更新
+    assert_eq!(&msg[..12], &[0, 7, 0x28, 0, 0, 1, 0, 0, 0, 2, 0, 0]);
+    assert_eq!(&msg[msg.len() - 4..], &[1, 1, 1, 1]);
+}
+
+#[test]
+fn test_export_clash() {
+    let template = r#"
+proxies:
+  - name: cf-proxy-test
+    type: vless
+    server: 0.0.0.0
+    port: 443
+    servername: example.com
+    ws-opts:
+      path: /ws
+proxy-groups:
+  - name: auto
+    type: url-test
+    proxies: [DIRECT, cf-proxy-test]
+  - name: manual
+    type: select
+    proxies: [cf-proxy-test, DIRECT]
+"#;
+    let addrs = vec![
+        "1.1.1.1:443".parse().unwrap(),
+        "1.0.0.1:2053".parse().unwrap(),
+    ];
+    let output = export::render(
+        Format::Clash,
+        template,
+        "cf-proxy-test",
+        "auto",
+        &addrs,
+        "cf.xiu2.xyz",
+    )
+    .unwrap();
+
+    let doc: serde_yaml::Value = serde_yaml::from_str(&output).unwrap();
+    let proxies = doc["proxies"].as_sequence().unwrap();
+    assert_eq!(proxies.len(), 2);
+    assert_eq!(proxies[1]["name"].as_str(), Some("cf-proxy-test-2"));
+    assert_eq!(proxies[1]["server"].as_str(), Some("1.0.0.1"));
+    assert_eq!(proxies[1]["port"].as_u64(), Some(2053));
+    assert_eq!(proxies[1]["servername"].as_str(), Some("cf.xiu2.xyz"));
+    assert_eq!(
+        proxies[1]["ws-opts"]["headers"]["Host"].as_str(),
+        Some("cf.xiu2.xyz")
+    );
+
+    let group: Vec<&str> = doc["proxy-groups"][0]["proxies"]
+        .as_sequence()
+        .unwrap()
+        .iter()
+        .map(|x| x.as_str().unwrap())
+        .collect();
+    assert_eq!(group, vec!["DIRECT", "cf-proxy-test-1", "cf-proxy-test-2"]);
+    // 其他分组中的占位节点同样被替换
+    let manual: Vec<&str> = doc["proxy-groups"][1]["proxies"]
+        .as_sequence()
+        .unwrap()
+        .iter()
+        .map(|x| x.as_str().unwrap())
+        .collect();
+    assert_eq!(manual, vec!["cf-proxy-test-1", "cf-proxy-test-2", "DIRECT"]);
+}
+
+#[test]
+fn test_export_xray() {
+    let template = r#"{
+  "outbounds": [
+    {
+      "tag": "cf-proxy-test",
+      "protocol": "vless",
+      "settings": {"vnext": [{"address": "0.0.0.0", "port": 443}]},
+      "streamSettings": {"network": "ws", "security": "none", "wsSettings": {"path": "/ws"}}
+    }
+  ],
+  "routing": {
+    "balancers": [{"tag": "other", "selector": ["cf-proxy-test"]}]
+  }
+}"#;
+    let addrs = vec!["1.1.1.1:80".parse().unwrap()];
+    let output = export::render(
+        Format::Xray,
+        template,
+        "cf-proxy-test",
+        "",
+        &addrs,
+        "cf.xiu2.xyz",
+    )
+    .unwrap();
+
+    let doc: serde_json::Value = serde_json::from_str(&output).unwrap();
+    let node = &doc["outbounds"][0];
+    assert_eq!(node["settings"]["vnext"][0]["address"], "1.1.1.1");
+    assert_eq!(
+        node["streamSettings"]["wsSettings"]["headers"]["Host"],
+        "cf.xiu2.xyz"
+    );
+    // security 为 none 时不写入 TLS 设置
+    assert!(node["streamSettings"].get("noneSettings").is_none());
+    assert_eq!(
+        doc["routing"]["balancers"][0]["selector"],
+        serde_json::json!(["cf-proxy-test-1"])
+    );
+    assert!(export::render(Format::Xray, template, "cf-proxy-test", "auto", &addrs, "x").is_err());
+}
+
+fn summary() -> RunSummary {
+    let mut summary = RunSummary {
+        time: 1,
+        best_ip: Some("1.1.1.1".parse().unwrap()),
+        best_port: Some(443),
+        latency: Some(120.0),
+        speed: Some(2.0 * 1024.0 * 1024.0),
+        colo: Some("LAX".to_string()),
+        latency_delta: None,
+        speed_delta: None,
+        format: SpeedFormat::default(),
+    };
+    let mut prev = summary.clone();
+    prev.latency = Some(150.0);
+    summary.compare(&prev);
+    summary
+}
+
+#[test]
+fn test_notify_webhook() {
+    let (addr, log) = mock_server(|_, _| String::new());
+    let conf = WebhookConfig {
+        url: format!("http://{addr}/hook"),
+        template:
+            r#"{"ip":"{{best_ip}}","latency":"{{latency}} ({{latency_delta}})","text":"{{text}}"}"#
+                .to_string(),
+        ..Default::default()
+    };
+
+    tokio::runtime::Runtime::new()
+        .unwrap()
+        .block_on(notify::send_with_retry(
+            &WebhookNotifier::build(&conf),
+            &summary(),
+        ))
+        .unwrap();
+
+    let log = log.lock().unwrap();
+    assert_eq!(log.len(), 1);
+    let body = log[0].strip_prefix("POST /hook ").unwrap();
+    let body: serde_json::Value = serde_json::from_str(body).unwrap();
+    assert_eq!(body["ip"], "1.1.1.1");
+    assert_eq!(body["latency"], "120ms (-30ms)");
+    assert!(body["text"].as_str().unwrap().contains("LAX"));
+}
+
+#[test]
+fn test_notify_smtp() {
+    use std::io::{BufRead, BufReader, Write};
+
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let stub = std::thread::spawn(move || {
+        let (stream, _) = listener.accept().unwrap();
+        let mut writer = stream.try_clone().unwrap();
+        let mut reader = BufReader::new(stream);
+        let mut received = Vec::new();
+        writer.write_all(b"220 stub\r\n").unwrap();
+        let mut in_data = false;
+        loop {
+            let mut line = String::new();
+            if reader.read_line(&mut line).unwrap() == 0 {
+                break;
+            }
+            let line = line.trim_end().to_string();
+            received.push(line.clone());
+            let reply: &[u8] = if in_data {
+                if line != "." {
+                    continue;
+                }
+                in_data = false;
+                b"250 queued\r\n"
+            } else if line.starts_with("EHLO") {
+                b"250-stub\r\n250 OK\r\n"
+            } else if line == "DATA" {
+                in_data = true;
+                b"354 go ahead\r\n"
+            } else if line == "QUIT" {
+                writer.write_all(b"221 bye\r\n").unwrap();
+                break;
+            } else {
+                b"250 OK\r\n"
+            };
+            writer.write_all(reply).unwrap();
+        }
+        received
+    });
+
+    let conf = SmtpConfig {
+        server: addr.to_string(),
+        from: "tester@example.com".to_string(),
+        to: vec!["ops@example.com".to_string()],
+        ..Default::default()
+    };
+    tokio::runtime::Runtime::new()
+        .unwrap()
+        .block_on(notify::send_with_retry(
+            &SmtpNotifier::build(&conf),
+            &summary(),
+        ))
+        .unwrap();
+
+    let received = stub.join().unwrap();
+    assert!(received.contains(&"MAIL FROM:<tester@example.com>".to_string()));
+    assert!(received.contains(&"RCPT TO:<ops@example.com>".to_string()));
+    assert!(received.iter().any(|x| x.contains("1.1.1.1")));
+    assert!(
+        received.contains(&"Subject: =?UTF-8?B?Y2YtcHJveHktdGVzdCDmtYvpgJ/nu5Pmnpw=?=".to_string())
+    );
+    assert!(received.iter().any(|x| x.starts_with("Date: ")));
+    assert!(received
+        .iter()
+        .any(|x| x.starts_with("Message-ID: <") && x.ends_with("@example.com>")));
+    assert!(received.contains(&"MIME-Version: 1.0".to_string()));
+    assert!(received.contains(&"Content-Transfer-Encoding: 8bit".to_string()));
+    assert_eq!(smtp::format_date(0), "Thu, 01 Jan 1970 00:00:00 +0000");
+    assert_eq!(
+        smtp::format_date(1_792_411_200),
+        "Mon, 19 Oct 2026 12:00:00 +0000"
+    );
+
+    assert_eq!(smtp::dot_stuff(".a\nb\n..c"), "..a\nb\n...c");
+    let long = smtp::encode_header(&"测速".repeat(20));
+    assert!(long.split("\r\n ").all(|x| x.len() <= 75));
+}
+
+#[test]
+fn test_server_api() {
+    let conf = example_config();
+
+    let (tx, rx) = mpsc::channel();
+    let ctx = server::Context {
+        conf: Arc::new(conf),
+        state: Arc::new(Mutex::new(server::State::default())),
+        trigger: tx,
+    };
+    let get = |path: &str| Request::get(path).body(Body::empty()).unwrap();
+
+    assert_eq!(server::handle(&ctx, &get("/best")).status(), 404);
+    assert_eq!(server::handle(&ctx, &get("/healthz")).status(), 200);
+
+    ctx.state.lock().unwrap().latest = Some(RunReport {
+        summary: summary(),
+        conn: Vec::new(),
+        download: Vec::new(),
+        upload: Vec::new(),
+        conn_failed: Vec::new(),
+        download_failed: Vec::new(),
+        upload_failed: Vec::new(),
+        duration: 0.0,
+        comparison: Vec::new(),
+    });
+    let resp = server::handle(&ctx, &get("/best"));
+    assert_eq!(resp.status(), 200);
+    let body = tokio::runtime::Runtime::new()
+        .unwrap()
+        .block_on(hyper::body::to_bytes(resp.into_body()))
+        .unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(body["best_ip"], "1.1.1.1");
+    assert_eq!(body["colo"], "LAX");
+
+    let run = || Request::post("/run").body(Body::empty()).unwrap();
+    assert_eq!(server::handle(&ctx, &run()).status(), 202);
+    assert!(rx.try_recv().is_ok());
+    // 测速还在进行时不会重复触发
+    assert_eq!(server::handle(&ctx, &run()).status(), 409);
+
+    // 持有锁的线程 panic 后接口仍然可用
+    let state = ctx.state.clone();
+    let _ = std::thread::spawn(move || {
+        let _guard = state.lock().unwrap();
+        panic!("poison");
+    })
+    .join();
+    assert!(ctx.state.is_poisoned());
+    assert_eq!(server::handle(&ctx, &get("/healthz")).status(), 200);
+}
+
+#[test]
+fn test_metrics_render() {
+    let ip: std::net::IpAddr = "1.1.1.1".parse().unwrap();
+    let mut report = RunReport {
+        summary: summary(),
+        conn: vec![ConnReportEntry {
+            ip,
+            port: 443,
+            latency: 100.0,
+            loss: 0.0,
+            jitter: 0.0,
+            retries: 0,
+            colo: Some("LAX".to_string()),
+            protocol: None,
+            quic: None,
+            tags: Vec::new(),
+        }],
+        download: Vec::new(),
+        upload: Vec::new(),
+        conn_failed: vec!["1.0.0.1:443".parse().unwrap()],
+        download_failed: Vec::new(),
+        upload_failed: Vec::new(),
+        duration: 42.0,
+        comparison: Vec::new(),
+    };
+
+    let report_conn = report.conn[0].clone();
+    let mut metrics = Metrics::default();
+    metrics.observe(&report);
+    report.conn.clear();
+    report.conn_failed = vec![std::net::SocketAddr::new(ip, 443)];
+    metrics.observe(&report);
+
+    let out = metrics.render();
+    assert!(out.contains(
+        r#"cf_proxy_test_latency_seconds{ip="1.1.1.1",port="443",colo="LAX",quantile="0.5"} 0.1"#
+    ));
+    assert!(out.contains(r#"cf_proxy_test_loss_ratio{ip="1.1.1.1",port="443",colo="LAX"} 0.5"#));
+    assert!(out.contains(r#"cf_proxy_test_loss_ratio{ip="1.0.0.1",port="443",colo=""} 1"#));
+    assert!(out.contains(r#"cf_proxy_test_probes_total{phase="conn",outcome="failure"} 2"#));
+    assert!(out.contains(r#"cf_proxy_test_scan_duration_seconds_bucket{le="60"} 2"#));
+    assert!(out.contains(r#"cf_proxy_test_scan_duration_seconds_bucket{le="30"} 0"#));
+    assert!(out.contains("# TYPE cf_proxy_test_latency_seconds summary"));
+    assert!(out
+        .contains(r#"cf_proxy_test_latency_seconds_count{ip="1.1.1.1",port="443",colo="LAX"} 1"#));
+
+    // _sum 和 _count 为累计值，样本移出窗口后不会减少
+    report.conn_failed.clear();
+    report.conn = vec![ConnReportEntry {
+        colo: Some("L\"A\nX".to_string()),
+        ..report_conn.clone()
+    }];
+    for _ in 0..metrics::METRICS_WINDOW {
+        metrics.observe(&report);
+    }
+    let out = metrics.render();
+    let labels = r#"ip="1.1.1.1",port="443",colo="L\"A\nX""#;
+    assert!(out.contains(&format!(
+        "cf_proxy_test_latency_seconds_count{{{labels}}} 21"
+    )));
+    assert!(out.contains(&format!("cf_proxy_test_loss_ratio{{{labels}}} 0")));
+    report.conn = Vec::new();
+    report.conn_failed = vec![std::net::SocketAddr::new(ip, 443)];
+
+    // 长时间没有出现的地址不再导出
+    for _ in 0..metrics::METRICS_EXPIRE {
+        metrics.observe(&report);
+    }
+    let out = metrics.render();
+    assert!(!out.contains(r#"ip="1.0.0.1""#));
+    assert!(out.contains(r#"ip="1.1.1.1""#));
+}
+
+#[test]
+fn test_load_sources() {
+    let mut dir = std::env::temp_dir();
+    dir.push(format!("cf-proxy-test-sources-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("a.txt"), "# comment\n1.1.1.1\n\n1.0.0.1\n").unwrap();
+    std::fs::write(dir.join("b.txt"), "1.0.0.1\n  104.16.0.1  \n").unwrap();
+    let single = dir.join("a.txt").to_str().unwrap().to_string();
+
+    let list = source::load(
+        &[
+            Source {
+                tag: "dir".to_string(),
+                ..Source::new(dir.to_str().unwrap())
+            },
+            Source::new(&single),
+        ],
+        &FetchConfig::default(),
+        &CidrConfig::default(),
+    );
+
+    let ips: Vec<String> = list.ips.iter().map(|x| x.to_string()).collect();
+    assert_eq!(ips, vec!["1.1.1.1", "1.0.0.1", "104.16.0.1"]);
+    assert_eq!(
+        list.tags_of(&"1.1.1.1".parse().unwrap()),
+        vec!["dir".to_string(), single]
+    );
+    assert_eq!(list.tags_of(&"104.16.0.1".parse().unwrap()), vec!["dir"]);
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_remote_source_cache() {
+    let hits = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+    let server_hits = hits.clone();
+    let (addr, _) = mock_http_server(move |headers, _, _| {
+        let n = server_hits.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+        match n {
+            0 => Response::builder()
+                .header("etag", "\"v1\"")
+                .body(Body::from("1.1.1.1\n1.0.0.0/30\n"))
+                .unwrap(),
+            1 => {
+                assert_eq!(headers.get("if-none-match").unwrap(), "\"v1\"");
+                Response::builder().status(304).body(Body::empty()).unwrap()
+            }
+            _ => Response::builder().status(500).body(Body::empty()).unwrap(),
+        }
+    });
+
+    let mut cache_dir = std::env::temp_dir();
+    cache_dir.push(format!("cf-proxy-test-cache-{}", std::process::id()));
+    let conf = FetchConfig {
+        cache_dir: cache_dir.to_str().unwrap().to_string(),
+        timeout: 5,
+    };
+    let url = format!("http://{addr}/ips-v4");
+
+    // 首次下载，之后分别是未变化和服务端出错，都使用缓存
+    for _ in 0..3 {
+        let content = remote::fetch(&url, &conf).unwrap();
+        let ips = source::parse_ips(&content, &url, &CidrConfig::default());
+        let ips: Vec<String> = ips.iter().map(|x| x.to_string()).collect();
+        assert_eq!(ips, vec!["1.1.1.1", "1.0.0.1"]);
+    }
+    assert_eq!(hits.load(std::sync::atomic::Ordering::SeqCst), 3);
+
+    std::fs::remove_dir_all(&cache_dir).unwrap();
+}
+
+#[test]
+fn test_cidr_expand() {
+    let ips = cidr::expand("104.16.0.0/22".parse().unwrap(), 1, 4096);
+    let ips: Vec<String> = ips.iter().map(|x| x.to_string()).collect();
+    assert_eq!(
+        ips,
+        vec!["104.16.0.1", "104.16.1.1", "104.16.2.1", "104.16.3.1"]
+    );
+
+    assert_eq!(
+        cidr::expand("104.16.0.0/24".parse().unwrap(), 0, 1).len(),
+        254
+    );
+    // 取全部地址时跳过网络地址和广播地址
+    let ips = cidr::expand("104.16.0.0/24".parse().unwrap(), 0, 1);
+    assert_eq!(ips.first().unwrap().to_string(), "104.16.0.1");
+    assert_eq!(ips.last().unwrap().to_string(), "104.16.0.254");
+    assert_eq!(
+        cidr::expand("2606:4700::/32".parse().unwrap(), 0, 1).len(),
+        cidr::V6_DENSE_LIMIT
+    );
+    assert_eq!(
+        cidr::expand("104.16.0.0/16".parse().unwrap(), 2, 16).len(),
+        32
+    );
+    assert_eq!(
+        cidr::expand("2606:4700::/32".parse().unwrap(), 1, 8).len(),
+        8
+    );
+}
+
+#[test]
+fn test_exclude() {
+    let mut list = source::IpList::default();
+    for ip in ["1.1.1.1", "1.0.0.1", "104.16.0.1", "104.17.0.1"] {
+        list.push(ip.parse().unwrap(), "test");
+    }
+
+    let nets = source::parse_nets("# comment\n1.1.1.1\n104.16.0.0/13\n", "test");
+    assert_eq!(list.exclude(&nets), 3);
+    assert_eq!(
+        list.ips,
+        vec!["1.0.0.1".parse::<std::net::IpAddr>().unwrap()]
+    );
+    assert!(list.tags_of(&"1.1.1.1".parse().unwrap()).is_empty());
+}
+
+#[test]
+fn test_multi_port_tcp() {
+    let mut conf = example_config();
+
+    let a = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let b = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let closed = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let closed_port = closed.local_addr().unwrap().port();
+    drop(closed);
+
+    conf.conn.method = "tcp".to_string();
+    conf.ports = vec![
+        a.local_addr().unwrap().port(),
+        b.local_addr().unwrap().port(),
+        closed_port,
+    ];
+    let result = conf
+        .create_conn_test_client(vec!["127.0.0.1".parse().unwrap()])
+        .connect_test();
+
+    assert_eq!(result.top_addrs().len(), 2);
+    assert_eq!(result.best_ports().len(), 1);
+    assert_eq!(
+        result.failed(),
+        &[SocketAddr::new("127.0.0.1".parse().unwrap(), closed_port)]
+    );
+}
+
+#[test]
+fn test_plain_http_target() {
+    let (addr, log) = mock_http_server(|headers, _, _| {
+        let host = headers.get("host").unwrap().to_str().unwrap();
+        if !host.starts_with("cf.example.com:") {
+            return Response::builder().status(400).body(Body::empty()).unwrap();
+        }
+        Response::builder()
+            .header("cf-ray", "7d1a2b3c4d5e6f70-SJC")
+            .body(Body::from(vec![0u8; 64 * 1024]))
+            .unwrap()
+    });
+
+    let mut conf = example_config();
+    // 测试地址是 https，强制使用 http 并通过 resolve 连接到本地服务
+    conf.url = "https://cf.example.com/download".to_string();
+    conf.scheme = "http".to_string();
+    conf.ports = vec![addr.port()];
+
+    let result = conf.create_conn_test_client(vec![addr.ip()]).connect_test();
+    assert_eq!(result.top_addrs(), vec![addr]);
+    assert_eq!(result.list()[0].colo.as_deref(), Some("SJC"));
+
+    let download = conf
+        .create_download_test_client(result.top_addrs())
+        .download_test();
+    assert_eq!(download.best_ip(), Some(addr.ip()));
+
+    let log = log.lock().unwrap();
+    assert!(log[0].starts_with("HEAD /download"));
+    assert!(log[1].starts_with("GET /download"));
+}
+
+#[test]
+fn test_protocol_pinning() {
+    let (addr, _) = mock_http_server(|_, _, _| Response::new(Body::from(vec![0u8; 1024])));
+
+    let mut conf = example_config();
+    conf.url = "http://cf.example.com/download".to_string();
+    conf.scheme = "http".to_string();
+    conf.ports = vec![addr.port()];
+
+    for (protocol, expected) in [("http1", "HTTP/1.1"), ("http2", "HTTP/2.0")] {
+        conf.protocol = protocol.to_string();
+        let result = conf.create_conn_test_client(vec![addr.ip()]).connect_test();
+        assert_eq!(result.list()[0].protocol.as_deref(), Some(expected));
+
+        let download = conf
+            .create_download_test_client(result.top_addrs())
+            .download_test();
+        let list = download.list.unwrap();
+        assert_eq!(list[0].protocol.as_deref(), Some(expected));
+    }
+}
+
+#[test]
+fn test_quic_probe() {
+    // 收到探测包后回复版本协商包，支持 QUIC v1
+    let server = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+    let addr = server.local_addr().unwrap();
+    std::thread::spawn(move || loop {
+        let mut buf = [0u8; 1500];
+        let (n, peer) = server.recv_from(&mut buf).unwrap();
+        let packet = &buf[..n];
+        assert_eq!(n, 1200);
+        let dcid_len = packet[5] as usize;
+        let dcid = &packet[6..6 + dcid_len];
+        let scid_len = packet[6 + dcid_len] as usize;
+        let scid = &packet[7 + dcid_len..7 + dcid_len + scid_len];
+
+        let mut resp = vec![0x80, 0, 0, 0, 0];
+        resp.push(scid.len() as u8);
+        resp.extend_from_slice(scid);
+        resp.push(dcid.len() as u8);
+        resp.extend_from_slice(dcid);
+        resp.extend_from_slice(&0xff00_001du32.to_be_bytes());
+        resp.extend_from_slice(&1u32.to_be_bytes());
+        server.send_to(&resp, peer).unwrap();
+    });
+
+    let mut conf = example_config();
+    conf.conn.method = "quic".to_string();
+    conf.conn.timeout = 2;
+    conf.ports = vec![addr.port()];
+
+    let result = conf.create_conn_test_client(vec![addr.ip()]).connect_test();
+    assert_eq!(result.top_addrs(), vec![addr]);
+    assert_eq!(result.list()[0].protocol.as_deref(), Some("QUIC v1"));
+
+    // 没有回复时视为连接失败
+    let silent = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+    conf.ports = vec![silent.local_addr().unwrap().port()];
+    conf.conn.timeout = 1;
+    let result = conf.create_conn_test_client(vec![addr.ip()]).connect_test();
+    assert!(result.list().is_empty());
+    assert_eq!(result.failed().len(), 1);
+
+    // http3 时仍按 conn.method 测试，QUIC 探测的结果单独记录
+    let _tcp = std::net::TcpListener::bind(addr).unwrap();
+    conf.ports = vec![addr.port()];
+    conf.conn.method = "tcp".to_string();
+    conf.protocol = "http3".to_string();
+    conf.phases = vec!["conn".to_string()];
+    conf.history.path = String::new();
+    let mut ips = IpList::default();
+    ips.push(addr.ip(), "local");
+    let report = runner::run(&conf, &ips);
+    assert_eq!(report.conn.len(), 1);
+    assert_eq!(report.conn[0].protocol, None);
+    assert!(report.conn[0].quic.is_some());
+
+    conf.phases = vec!["conn".to_string(), "download".to_string()];
+    assert!(conf.validate().iter().any(|x| x.starts_with("protocol:")));
+}
+
+#[test]
+fn test_upload() {
+    let (addr, log) = mock_http_server(|_, line, _| {
+        if !line.starts_with("POST") {
+            return Response::builder().status(405).body(Body::empty()).unwrap();
+        }
+        // 等待响应的时间不计入上传速度
+        std::thread::sleep(Duration::from_millis(500));
+        Response::builder()
+            .header("cf-ray", "7d1a2b3c4d5e6f70-HKG")
+            .body(Body::empty())
+            .unwrap()
+    });
+
+    let mut conf = example_config();
+    conf.scheme = "http".to_string();
+    conf.upload.url = "http://cf.example.com/upload".to_string();
+    conf.upload.size = 200_000;
+
+    let result = conf.create_upload_test_client(vec![addr]).upload_test();
+    let list = result.list.unwrap();
+    let best = &list[0];
+    assert_eq!(best.addr(), addr);
+    assert_eq!(best.colo.as_deref(), Some("HKG"));
+    assert!(best.speed.byte_value() > 200_000.0 / 0.5);
+
+    let log = log.lock().unwrap();
+    assert!(log[0].starts_with("POST /upload "));
+    assert_eq!(log[0].len(), "POST /upload ".len() + 200_000);
+}
+
+#[test]
+fn test_multi_stream_download() {
+    let (addr, log) = mock_http_server(|_, _, _| Response::new(Body::from(vec![0u8; 256 * 1024])));
+
+    let mut conf = example_config();
+    conf.url = "http://cf.example.com/download".to_string();
+    conf.download.streams = 3;
+
+    let download = conf.create_download_test_client(vec![addr]).download_test();
+    let list = download.list.unwrap();
+    let single = list[0].single_speed.as_ref().unwrap();
+    assert!(single.byte_value() > 0.0);
+    assert!(list[0].speed.byte_value() > 0.0);
+
+    // 单连接 1 次，多连接 3 次
+    assert_eq!(log.lock().unwrap().len(), 4);
+
+    // 响应体没有传完连接就断开时视为下载失败
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    std::thread::spawn(move || {
+        use std::io::{Read, Write};
+        for stream in listener.incoming() {
+            let mut stream = stream.unwrap();
+            let mut buf = [0u8; 4096];
+            let _ = stream.read(&mut buf);
+            let _ = stream.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 1048576\r\n\r\n");
+            let _ = stream.write_all(&[0u8; 1024]);
+        }
+    });
+    conf.download.streams = 1;
+    let download = conf.create_download_test_client(vec![addr]).download_test();
+    assert!(download.list.is_none());
+    assert_eq!(download.failed, vec![addr]);
+}
+
+#[test]
+fn test_speed_series() {
+    let series = SpeedSeries {
+        interval: 0.25,
+        warmup: 2,
+        samples: vec![1.0, 2.0, 10.0, 20.0, 30.0, 40.0],
+    };
+    // 预热的两个采样不参与统计
+    let x = series.stability().unwrap();
+    assert_eq!(x.peak, 40.0);
+    assert_eq!(x.mean, 25.0);
+    assert_eq!(x.p10, 10.0);
+    assert!((x.cv - 125f64.sqrt() / 25.0).abs() < 1e-9);
+
+    // 下载在预热阶段结束时使用全部采样
+    let short = SpeedSeries {
+        interval: 0.25,
+        warmup: 4,
+        samples: vec![2.0, 4.0],
+    };
+    assert_eq!(short.stability().unwrap().mean, 3.0);
+    assert!(SpeedSeries::default().stability().is_none());
+
+    let merged = SpeedSeries::merge(&[series, short]);
+    assert_eq!(merged.warmup, 4);
+    assert_eq!(merged.samples, vec![3.0, 6.0, 10.0, 20.0, 30.0, 40.0]);
+
+    // 不满一个采样间隔就结束的下载也有采样
+    let (addr, _) = mock_http_server(|_, _, _| Response::new(Body::from(vec![0u8; 1024])));
+    let mut conf = example_config();
+    conf.url = "http://cf.example.com/download".to_string();
+    conf.download.sample_interval = 60_000;
+    let download = conf.create_download_test_client(vec![addr]).download_test();
+    assert_eq!(download.list.unwrap()[0].series.samples.len(), 1);
+}
+
+#[test]
+fn test_loaded_latency() {
+    // 分块慢慢返回，让下载持续一段时间
+    let (addr, _) = mock_http_server(|_, _, _| {
+        let chunks = futures::stream::unfold(0, |i| async move {
+            if i >= 6 {
+                return None;
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            Some((Ok::<_, std::io::Error>(vec![0u8; 16 * 1024]), i + 1))
+        });
+        Response::new(Body::wrap_stream(chunks))
+    });
+
+    let mut conf = example_config();
+    conf.url = "http://cf.example.com/download".to_string();
+    conf.download.latency_probe = 100;
+
+    let download = conf.create_download_test_client(vec![addr]).download_test();
+    let list = download.list.unwrap();
+    assert!(list[0].idle_latency.is_some());
+    assert!(list[0].loaded_latency.is_some());
+
+    // 下载结束后不再等待下一次探测
+    conf.download.latency_probe = 5000;
+    let start = std::time::Instant::now();
+    conf.create_download_test_client(vec![addr]).download_test();
+    assert!(start.elapsed() < Duration::from_secs(3));
+}
+
+#[test]
+fn test_speed_format() {
+    let speed = Speed::from_bytes(12_500_000.0);
+    let format = |unit, prefix| speed.format(SpeedFormat { unit, prefix });
+    assert_eq!(format(SpeedUnit::Bit, SpeedPrefix::Si), "100.00 Mbps");
+    assert_eq!(format(SpeedUnit::Byte, SpeedPrefix::Si), "12.50 MB/s");
+    assert_eq!(format(SpeedUnit::Byte, SpeedPrefix::Iec), "11.92 MiB/s");
+    assert_eq!(format(SpeedUnit::Bit, SpeedPrefix::Iec), "95.37 Mibit/s");
+    let small = Speed::from_bytes(512.0).format(SpeedFormat {
+        unit: SpeedUnit::Byte,
+        prefix: SpeedPrefix::Iec,
+    });
+    assert_eq!(small, "512.00 B/s");
+
+    // 出现 NaN 时排序不会 panic，NaN 排在最慢的位置
+    let mut list = [
+        Speed::from_bytes(1.0),
+        Speed::from_bytes(f64::NAN),
+        Speed::from_bytes(3.0),
+        Speed::byte_per_second(1024, Duration::ZERO),
+    ];
+    list.sort();
+    assert!(list[0].byte_value().is_nan());
+    assert_eq!(list[1].byte_value(), 0.0);
+    assert_eq!(list[3].byte_value(), 3.0);
+
+    // 结果按各自保存的格式显示，不依赖全局状态
+    let mut stat = DownloadTestStats::new("1.1.1.1:443".parse().unwrap(), speed);
+    assert!(stat.to_string().contains("11.92 MiB/s"));
+    stat.format = SpeedFormat {
+        unit: SpeedUnit::Bit,
+        prefix: SpeedPrefix::Si,
+    };
+    assert!(stat.to_string().contains("100.00 Mbps"));
+}
+
+#[test]
+fn test_rank() {
+    let mut conf = example_config();
+
+    let a = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let b = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let (a, b) = (a.local_addr().unwrap(), b.local_addr().unwrap());
+    conf.conn.method = "tcp".to_string();
+    conf.conn.pings = 3;
+    conf.ports = vec![a.port(), b.port()];
+    let conn = conf
+        .create_conn_test_client(vec!["127.0.0.1".parse().unwrap()])
+        .connect_test();
+    assert_eq!(conn.list().len(), 2);
+    assert!(conn.list().iter().all(|x| x.loss == 0.0));
+
+    let download = |slow: SocketAddr, fast: SocketAddr| DownloadTestResult {
+        top: 2,
+        list: Some(vec![
+            DownloadTestStats::new(slow, Speed::from_bytes(100.0)),
+            DownloadTestStats::new(fast, Speed::from_bytes(1000.0)),
+        ]),
+        failed: Vec::new(),
+    };
+
+    // 按速度排序
+    let mut result = download(a, b);
+    let strategy = rank::create_strategy(&conf.rank).unwrap();
+    rank::rank(strategy.as_ref(), &conn, &mut result);
+    assert_eq!(result.best().unwrap().addr(), b);
+    assert_eq!(result.best().unwrap().score, Some(1000.0));
+
+    // 只看延迟时，延迟最低的排在最前
+    conf.rank.strategy = "latency".to_string();
+    let fastest = conn.list()[0].addr();
+    let mut result = download(a, b);
+    let strategy = rank::create_strategy(&conf.rank).unwrap();
+    rank::rank(strategy.as_ref(), &conn, &mut result);
+    assert_eq!(result.best().unwrap().addr(), fastest);
+
+    // 只有速度有权重时与按速度排序相同，得分为 0~100
+    conf.rank.strategy = "weighted".to_string();
+    conf.rank.weights = RankWeights {
+        latency: 0.0,
+        loss: 0.0,
+        jitter: 0.0,
+        speed: 1.0,
+    };
+    let mut result = download(a, b);
+    let strategy = rank::create_strategy(&conf.rank).unwrap();
+    rank::rank(strategy.as_ref(), &conn, &mut result);
+    let list = result.list.unwrap();
+    assert_eq!(list[0].addr(), b);
+    assert_eq!(list[0].score, Some(100.0));
+    assert_eq!(list[1].score, Some(0.0));
+
+    let x = |latency, speed| Candidate {
+        latency: Some(latency),
+        speed,
+        ..Default::default()
+    };
+    let all = [x(10.2, 100.0), x(9.8, 50.0), x(20.0, 1000.0)];
+    let lex = LexicographicStrategy;
+    // 延迟取整后相同，按速度比较
+    assert!(lex.score(&all[0], &all) > lex.score(&all[1], &all));
+    assert!(lex.score(&all[1], &all) > lex.score(&all[2], &all));
+
+    conf.rank.strategy = "random".to_string();
+    assert!(rank::create_strategy(&conf.rank).is_err());
+    assert!(conf.validate().iter().any(|x| x.starts_with("rank:")));
+
+    // 权重不能为负数，在测速之前由 validate 报告
+    conf.rank.strategy = "weighted".to_string();
+    conf.rank.weights.speed = -1.0;
+    assert!(rank::create_strategy(&conf.rank).is_err());
+    assert!(conf.validate().iter().any(|x| x.starts_with("rank:")));
+}
+
+#[test]
+fn test_phases() {
+    let (addr, log) = mock_http_server(|_, _, _| Response::new(Body::from(vec![0u8; 1024])));
+
+    let mut conf = example_config();
+    conf.url = "http://cf.example.com/download".to_string();
+    conf.ports = vec![addr.port()];
+    conf.history.path = String::new();
+    let mut ips = IpList::default();
+    ips.push(addr.ip(), "local");
+
+    // 只测延迟
+    conf.phases = vec!["conn".to_string()];
+    let report = runner::run(&conf, &ips);
+    assert_eq!(report.conn.len(), 1);
+    assert!(report.download.is_empty());
+    assert_eq!(report.summary.best_ip, Some(addr.ip()));
+    assert_eq!(report.summary.speed, None);
+    assert!(log.lock().unwrap().iter().all(|x| x.starts_with("HEAD")));
+
+    // 跳过连通性测试，直接下载
+    log.lock().unwrap().clear();
+    conf.phases = vec!["download".to_string()];
+    let report = runner::run(&conf, &ips);
+    assert!(report.conn.is_empty());
+    assert_eq!(report.download.len(), 1);
+    assert_eq!(report.summary.latency, None);
+    assert!(report.summary.speed.is_some());
+    assert!(log.lock().unwrap().iter().all(|x| x.starts_with("GET")));
+
+    assert!(Phase::parse("ping").is_err());
+    // 上传测试需要下载测试的结果
+    conf.phases = vec!["upload".to_string()];
+    assert!(conf.validate().iter().any(|x| x.starts_with("phases:")));
+    conf.phases = vec!["conn".to_string()];
+    conf.upload.enable = true;
+    assert!(conf.validate().iter().any(|x| x.starts_with("phases:")));
+    conf.upload.enable = false;
+    let matches = |x: &[&str]| args::new_cmd().try_get_matches_from(x);
+    assert!(matches(&["cf-proxy-test", "--phases", "upload"]).is_err());
+    assert!(matches(&["cf-proxy-test", "--phases", "conn,ping"]).is_err());
+    assert!(matches(&["cf-proxy-test", "--phases", "download,upload"]).is_ok());
+    // 无效的阶段不会被忽略
+    conf.phases = vec!["conn".to_string(), "ping".to_string()];
+    assert!(std::panic::catch_unwind(|| runner::phases(&conf)).is_err());
+}
+
+#[test]
+fn test_subcommands() {
+    let parse = |x: &[&str]| Command::parse(args::new_cmd().try_get_matches_from(x).unwrap());
+
+    // 不带子命令时为 test
+    let cmd = parse(&["cf-proxy-test", "-c", "a.yaml", "--no-download"]);
+    assert_eq!(cmd.action, Action::Test);
+    assert_eq!(cmd.conf_path, "a.yaml");
+    assert_eq!(cmd.phases, Some(vec!["conn".to_string()]));
+
+    // 全局参数可以写在子命令之后
+    let cmd = parse(&[
+        "cf-proxy-test",
+        "scan",
+        "-m",
+        "tcp",
+        "-s",
+        "x.txt",
+        "-s",
+        "y.txt",
+    ]);
+    assert_eq!(cmd.action, Action::Scan);
+    assert_eq!(cmd.method.as_deref(), Some("tcp"));
+    assert_eq!(cmd.ip_src, vec!["x.txt", "y.txt"]);
+
+    let cmd = parse(&["cf-proxy-test", "bench"]);
+    assert_eq!(cmd.phases, Some(vec!["download".to_string()]));
+    assert_eq!(parse(&["cf-proxy-test", "-d"]).action, Action::Daemon);
+    assert_eq!(
+        parse(&["cf-proxy-test", "history", "-n", "3"]).action,
+        Action::History(3)
+    );
+    assert_eq!(
+        parse(&["cf-proxy-test", "config", "init", "--force"]).action,
+        Action::ConfigInit(true)
+    );
+    assert!(args::new_cmd()
+        .try_get_matches_from(["cf-proxy-test", "config"])
+        .is_err());
+
+    // 默认配置是有效的
+    let mut path = std::env::temp_dir();
+    path.push(format!("cf-proxy-test-conf-{}.yaml", std::process::id()));
+    let path = path.to_str().unwrap().to_string();
+    Config::write_default(&path, false).unwrap();
+    assert!(Config::write_default(&path, false).is_err());
+    let mut conf = Config::load(&path).unwrap();
+    assert!(conf.validate().is_empty());
+    conf.scheme = "ftp".to_string();
+    conf.phases.push("ping".to_string());
+    assert_eq!(conf.validate().len(), 2);
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_result_source() {
+    let (addr, _) = mock_http_server(|_, _, _| Response::new(Body::from(vec![0u8; 1024])));
+
+    let mut conf = example_config();
+    conf.url = "http://cf.example.com/download".to_string();
+    conf.ports = vec![addr.port()];
+    conf.history.path = String::new();
+    let mut dir = std::env::temp_dir();
+    dir.push(format!("cf-proxy-test-result-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let csv = dir.join("last.csv").to_str().unwrap().to_string();
+    conf.result.path = csv.clone();
+
+    let mut ips = IpList::default();
+    ips.push(addr.ip(), "local");
+    let first = runner::run(&conf, &ips);
+    assert!(first.comparison.is_empty());
+
+    // 上次的结果中追加三个地址，按速度取前两个 ip，同一个 ip 的不同端口分别保留
+    let mut content = std::fs::read_to_string(&csv).unwrap();
+    assert!(content.starts_with("ip,port,latency,loss,jitter,speed,score,colo,protocol,tags\n"));
+    content.push_str("1.1.1.1,443,5.00,0.00,0.00,10.00,,\"LAX\",,a;b\n");
+    content.push_str("1.1.1.1,8443,5.00,0.00,0.00,20.00,,\"SJC\",,\n");
+    content.push_str("1.0.0.1,443,1.00,,,,,,,\n");
+    std::fs::write(&csv, content).unwrap();
+    let source = Source {
+        top: 2,
+        by: "speed".to_string(),
+        ..Source::new(&csv)
+    };
+    let ips = source::load(&[source], &FetchConfig::default(), &CidrConfig::default());
+    assert_eq!(ips.ips, vec![addr.ip(), "1.1.1.1".parse().unwrap()]);
+    assert_eq!(
+        ips.previous[&"1.1.1.1:443".parse().unwrap()]
+            .colo
+            .as_deref(),
+        Some("LAX")
+    );
+    assert_eq!(
+        ips.previous[&"1.1.1.1:8443".parse().unwrap()]
+            .colo
+            .as_deref(),
+        Some("SJC")
+    );
+
+    // 按延迟排序时没有延迟数据的地址排在最后
+    let source = Source {
+        top: 1,
+        by: "latency".to_string(),
+        ..Source::new(&csv)
+    };
+    let list = source::load(&[source], &FetchConfig::default(), &CidrConfig::default());
+    assert_eq!(list.ips, vec!["1.0.0.1".parse::<IpAddr>().unwrap()]);
+
+    // 再次测速时对比上次的结果，json 结果文件同样可以作为来源
+    let json = dir.join("next.json").to_str().unwrap().to_string();
+    conf.result.path = json.clone();
+    let mut local = IpList::default();
+    local.push(addr.ip(), "local");
+    local.previous = ips.previous.clone();
+    let second = runner::run(&conf, &local);
+    // 上次有而本次没有测试到的地址同样显示，本次的数据为空
+    assert_eq!(second.comparison.len(), 3);
+    assert_eq!(second.comparison[0].ip, addr.ip());
+    assert_eq!(second.comparison[1].port, 443);
+    assert_eq!(second.comparison[1].old_speed, Some(10.0));
+    assert_eq!(second.comparison[1].new_speed, None);
+    assert_eq!(second.comparison[1].new_latency, None);
+    // csv 中的数值保留两位小数
+    let old_speed = second.comparison[0].old_speed.unwrap();
+    assert!((old_speed - first.download[0].speed).abs() < 0.01);
+    assert_eq!(
+        second.comparison[0].new_speed,
+        Some(second.download[0].speed)
+    );
+
+    let list = source::load(
+        &[Source::new(&json)],
+        &FetchConfig::default(),
+        &CidrConfig::default(),
+    );
+    assert_eq!(list.ips, vec![addr.ip()]);
+    assert_eq!(list.previous[&addr].speed, Some(second.download[0].speed));
+
+    // 没有 port 列的 csv 按配置的端口对比
+    let no_port = dir.join("no-port.csv").to_str().unwrap().to_string();
+    std::fs::write(&no_port, "ip,speed\n1.1.1.1,5\n").unwrap();
+    let mut list = source::load(
+        &[Source::new(&no_port)],
+        &FetchConfig::default(),
+        &CidrConfig::default(),
+    );
+    list.set_default_port(443);
+    assert_eq!(
+        list.previous[&"1.1.1.1:443".parse().unwrap()].speed,
+        Some(5.0)
+    );
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_adaptive_scan() {
+    let mut conf = example_config();
+
+    // 只监听 127.0.0.1，同一块中的其他地址会立即连接失败
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    conf.conn.method = "tcp".to_string();
+    conf.ports = vec![listener.local_addr().unwrap().port()];
+    conf.phases = vec!["conn".to_string()];
+    conf.history.path = String::new();
+    conf.conn.adaptive = AdaptiveConfig {
+        enable: true,
+        probes: 2,
+        blocks: 1,
+        dense: 4,
+    };
+
+    let mut ips = IpList::default();
+    for ip in ["127.0.0.1", "127.0.0.200", "127.0.1.1", "127.0.1.2"] {
+        ips.push(ip.parse().unwrap(), "lo");
+    }
+    let result = conf.create_conn_test_client(ips.ips.clone()).connect_test();
+    let blocks = adaptive::rank_blocks(&ips.ips, &result);
+    assert_eq!(blocks.len(), 1);
+    assert_eq!(blocks[0].net.to_string(), "127.0.0.0/24");
+    assert_eq!(blocks[0].success, 0.5);
+
+    // 第二阶段只测试最好的一块中没有测试过的地址，并继承来源标签
+    let next = adaptive::plan(&ips, &result, &conf.conn.adaptive);
+    let next_ips: Vec<String> = next.ips.iter().map(|x| x.to_string()).collect();
+    assert_eq!(next_ips, vec!["127.0.0.64", "127.0.0.127", "127.0.0.190"]);
+    assert_eq!(next.tags_of(&next.ips[0]), vec!["lo"]);
+
+    let report = runner::run(&conf, &ips);
+    assert_eq!(report.conn.len(), 1);
+    assert_eq!(report.conn_failed.len(), 6);
+    assert!(report.conn_failed.contains(&SocketAddr::new(
+        "127.0.0.64".parse().unwrap(),
+        conf.ports[0]
+    )));
+
+    // 第二阶段同样排除 exclude 中的地址
+    conf.exclude = vec!["127.0.0.127".to_string()];
+    let report = runner::run(&conf, &ips);
+    assert_eq!(report.conn_failed.len(), 5);
+    assert!(!report.conn_failed.contains(&SocketAddr::new(
+        "127.0.0.127".parse().unwrap(),
+        conf.ports[0]
+    )));
+    conf.exclude.clear();
+
+    // 只有执行连通性测试时第一阶段才按 probes 取地址
+    let mut dir = std::env::temp_dir();
+    dir.push(format!("cf-proxy-test-adaptive-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let conf_path = dir.join("config.yaml");
+    let example = include_str!("config/example.yaml").replace(
+        "    # 是否启用，默认不启用\n    enable: false\n    # 第一阶段",
+        "    enable: true\n    # 第一阶段",
+    );
+    std::fs::write(&conf_path, example).unwrap();
+    let ip_path = dir.join("ip.txt");
+    std::fs::write(&ip_path, "10.0.0.0/24\n").unwrap();
+    let init = |phases: Option<Vec<String>>| {
+        let sources = vec![Source::new(ip_path.to_str().unwrap())];
+        Config::init(conf_path.to_str().unwrap(), sources, phases).1
+    };
+    assert_eq!(init(None).ips.len(), 2);
+    assert_eq!(init(Some(vec!["download".to_string()])).ips.len(), 1);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+// 前 failures 个连接读到请求后直接关闭，之后的连接正常返回 200
+fn flaky_http_server(failures: usize) -> SocketAddr {
+    failing_http_server(move |i| i < failures)
+}
+
+// 第 i 个连接在 fail(i) 为 true 时读到请求后直接关闭，否则正常返回 200
+fn failing_http_server(fail: impl Fn(usize) -> bool + Send + 'static) -> SocketAddr {
+    use std::io::{Read, Write};
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    std::thread::spawn(move || {
+        for (i, stream) in listener.incoming().enumerate() {
+            let mut stream = stream.unwrap();
+            let mut buf = [0u8; 1024];
+            let _ = stream.read(&mut buf);
+            if !fail(i) {
+                let _ = stream.write_all(b"HTTP/1.1 200 OK\r\ncontent-length: 0\r\n\r\n");
+            }
+        }
+    });
+    addr
+}
+
+#[test]
+fn test_retry() {
+    let refused = std::io::Error::from(std::io::ErrorKind::ConnectionRefused);
+    assert_eq!(ErrorKind::classify(&refused), ErrorKind::Refused);
+    let elapsed = tokio::runtime::Runtime::new().unwrap().block_on(async {
+        tokio::time::timeout(Duration::ZERO, std::future::pending::<()>())
+            .await
+            .unwrap_err()
+    });
+    assert_eq!(ErrorKind::classify(&elapsed), ErrorKind::Timeout);
+    assert!(ErrorKind::parse("dns").is_err());
+
+    let mut conf = example_config();
+    let flaky = flaky_http_server(1);
+    let clean = flaky_http_server(0);
+    conf.url = "http://cf.example.com/".to_string();
+    conf.ports = vec![flaky.port(), clean.port()];
+    conf.phases = vec!["conn".to_string()];
+    conf.history.path = String::new();
+    conf.rank.strategy = "latency".to_string();
+    let mut ips = IpList::default();
+    ips.push(flaky.ip(), "local");
+
+    // 默认不重试，连接被关闭的地址视为失败
+    let report = runner::run(&conf, &ips);
+    assert_eq!(report.conn_failed, vec![flaky]);
+
+    // 连接被关闭时重试，重试过的地址排在后面
+    let flaky = flaky_http_server(1);
+    conf.ports = vec![flaky.port(), clean.port()];
+    conf.retry.attempts = 3;
+    conf.retry.backoff = 10;
+    conf.retry.on = vec!["reset".to_string()];
+    assert_eq!(conf.retry_policy().delay(3), Duration::from_millis(40));
+    let report = runner::run(&conf, &ips);
+    assert!(report.conn_failed.is_empty());
+    let retries: Vec<_> = report.conn.iter().map(|x| (x.port, x.retries)).collect();
+    assert!(retries.contains(&(flaky.port(), 1)));
+    assert!(retries.contains(&(clean.port(), 0)));
+    assert_eq!(report.summary.best_port, Some(clean.port()));
+
+    // 不在 on 中的失败原因不重试
+    let flaky = flaky_http_server(1);
+    conf.ports = vec![flaky.port()];
+    conf.retry.on = vec!["timeout".to_string()];
+    let report = runner::run(&conf, &ips);
+    assert_eq!(report.conn_failed, vec![flaky]);
+
+    // 多连接下载中失败的连接同样重试，重试次数计入结果
+    let addr = failing_http_server(|i| i == 1);
+    conf.ports = vec![addr.port()];
+    conf.retry.on = vec!["reset".to_string()];
+    conf.download.streams = 2;
+    let result = conf.create_download_test_client(vec![addr]).download_test();
+    let stat = &result.list.unwrap()[0];
+    assert_eq!(stat.retries, 1);
+    assert!(stat.single_speed.is_some());
+}
+
+#[test]
+fn test_request_headers() {
+    let hosts: RequestLog = Arc::new(Mutex::new(Vec::new()));
+    let server_hosts = hosts.clone();
+    let (addr, _) = mock_http_server(move |headers, _, _| {
+        let header = |name: &str| {
+            headers
+                .get(name)
+                .map(|x| x.to_str().unwrap().to_string())
+                .unwrap_or_default()
+        };
+        server_hosts.lock().unwrap().push(header("host"));
+        if header("user-agent") != "probe/1.0"
+            || header("x-worker") != "edge"
+            || header("cookie") != "a=1; session=abc"
+        {
+            return Response::builder().status(403).body(Body::empty()).unwrap();
+        }
+        Response::new(Body::from(vec![0u8; 1024]))
+    });
+
+    let mut conf = example_config();
+    conf.url = "http://cf.example.com/download".to_string();
+    conf.ports = vec![addr.port()];
+    conf.history.path = String::new();
+    conf.request.user_agent = "probe/1.0".to_string();
+    conf.request
+        .headers
+        .insert("X-Worker".to_string(), "edge".to_string());
+    conf.request
+        .cookies
+        .insert("session".to_string(), "abc".to_string());
+    conf.request
+        .cookies
+        .insert("a".to_string(), "1".to_string());
+    let mut ips = IpList::default();
+    ips.push(addr.ip(), "local");
+
+    // 只设置 sni 时 Host 仍然是测试 URL 中的主机
+    conf.request.sni = "sni.example.com".to_string();
+    let report = runner::run(&conf, &ips);
+    assert_eq!(report.conn.len(), 1);
+    assert_eq!(report.download.len(), 1);
+    assert!(hosts.lock().unwrap().iter().all(|x| x == "cf.example.com"));
+
+    hosts.lock().unwrap().clear();
+    conf.request.host = "worker.example.com".to_string();
+    let report = runner::run(&conf, &ips);
+    assert_eq!(report.download.len(), 1);
+    assert_eq!(hosts.lock().unwrap().len(), 2);
+    assert!(hosts
+        .lock()
+        .unwrap()
+        .iter()
+        .all(|x| x == "worker.example.com"));
+
+    conf.request
+        .headers
+        .insert("bad header".to_string(), "x".to_string());
+    conf.request.sni = "1.1.1.1".to_string();
+    let errors = conf.validate();
+    assert!(errors.iter().any(|x| x.starts_with("request:")));
+    assert!(errors.iter().any(|x| x.starts_with("request.sni:")));
+}
+
+// 自签名证书，subject 和签发者都是 cn，SAN 为 cn
+fn self_signed(
+    cn: &str,
+) -> (
+    openssl::x509::X509,
+    openssl::pkey::PKey<openssl::pkey::Private>,
+) {
+    use openssl::x509::extension::SubjectAlternativeName;
+    let key = openssl::pkey::PKey::from_rsa(openssl::rsa::Rsa::generate(2048).unwrap()).unwrap();
+    let mut name = openssl::x509::X509NameBuilder::new().unwrap();
+    name.append_entry_by_text("CN", cn).unwrap();
+    let name = name.build();
+    let mut builder = openssl::x509::X509::builder().unwrap();
+    builder.set_version(2).unwrap();
+    builder.set_subject_name(&name).unwrap();
+    builder.set_issuer_name(&name).unwrap();
+    builder.set_pubkey(&key).unwrap();
+    let serial = openssl::bn::BigNum::from_u32(1).unwrap();
+    builder
+        .set_serial_number(&serial.to_asn1_integer().unwrap())
+        .unwrap();
+    builder
+        .set_not_before(&openssl::asn1::Asn1Time::days_from_now(0).unwrap())
+        .unwrap();
+    builder
+        .set_not_after(&openssl::asn1::Asn1Time::days_from_now(30).unwrap())
+        .unwrap();
+    let san = SubjectAlternativeName::new()
+        .dns(cn)
+        .build(&builder.x509v3_context(None, None))
+        .unwrap();
+    builder.append_extension(san).unwrap();
+    builder
+        .sign(&key, openssl::hash::MessageDigest::sha256())
+        .unwrap();
+    (builder.build(), key)
+}
+
+// 使用 cert 的 https 服务，client_ca 不为空时要求客户端证书
+fn tls_server(
+    cert: &openssl::x509::X509,
+    key: &openssl::pkey::PKey<openssl::pkey::Private>,
+    client_ca: Option<openssl::x509::X509>,
+) -> SocketAddr {
+    use openssl::ssl::{SslAcceptor, SslMethod, SslVerifyMode};
+    use std::io::{Read, Write};
+    let mut acceptor = SslAcceptor::mozilla_intermediate(SslMethod::tls()).unwrap();
+    acceptor.set_certificate(cert).unwrap();
+    acceptor.set_private_key(key).unwrap();
+    if let Some(ca) = client_ca {
+        acceptor.cert_store_mut().add_cert(ca).unwrap();
+        acceptor.set_verify(SslVerifyMode::PEER | SslVerifyMode::FAIL_IF_NO_PEER_CERT);
+    }
+    let acceptor = Arc::new(acceptor.build());
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            let acceptor = acceptor.clone();
+            std::thread::spawn(move || {
+                let mut stream = match acceptor.accept(stream.unwrap()) {
+                    Ok(stream) => stream,
+                    Err(_) => return,
+                };
+                let mut buf = [0u8; 4096];
+                if !matches!(stream.read(&mut buf), Ok(n) if n > 0) {
+                    return;
+                }
+                let mut resp =
+                    b"HTTP/1.1 200 OK\r\ncontent-length: 1024\r\nconnection: close\r\n\r\n"
+                        .to_vec();
+                if buf.starts_with(b"GET") {
+                    resp.extend_from_slice(&[0u8; 1024]);
+                }
+                let _ = stream.write_all(&resp);
+                let _ = stream.shutdown();
+            });
+        }
+    });
+    addr
+}
+
+#[test]
+fn test_tls_options() {
+    let (cert, key) = self_signed("cf.example.com");
+    let addr = tls_server(&cert, &key, None);
+
+    let mut dir = std::env::temp_dir();
+    dir.push(format!("cf-proxy-test-tls-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    // CA 文件中可以包含多个证书
+    let ca_file = dir.join("ca.pem");
+    let (other, _) = self_signed("other.example.com");
+    let mut pem = other.to_pem().unwrap();
+    pem.extend(cert.to_pem().unwrap());
+    std::fs::write(&ca_file, pem).unwrap();
+
+    let mut conf = example_config();
+    conf.url = "https://cf.example.com/download".to_string();
+    conf.ports = vec![addr.port()];
+    conf.history.path = String::new();
+    conf.protocol = "http1".to_string();
+    let mut ips = IpList::default();
+    ips.push(addr.ip(), "local");
+
+    // 默认验证证书，自签名证书连接失败
+    let report = runner::run(&conf, &ips);
+    assert!(report.conn.is_empty());
+
+    // 默认不记录 TLS 信息
+    conf.tls.verify = false;
+    let report = runner::run(&conf, &ips);
+    assert_eq!(report.download.len(), 1);
+    assert!(report.download[0].tls.is_none());
+
+    // 不验证证书，并记录 TLS 信息
+    conf.tls.inspect = true;
+    if cfg!(feature = "tls-inspect") {
+        let report = runner::run(&conf, &ips);
+        let tls = report.download[0].tls.clone().unwrap();
+        assert!(tls.version.starts_with("TLSv1."));
+        assert_eq!(tls.issuer, "cf.example.com");
+        assert!((29..=30).contains(&tls.days_left));
+    } else {
+        assert!(conf.validate().iter().any(|x| x.starts_with("tls.inspect")));
+        conf.tls.inspect = false;
+    }
+
+    // inspect 与下载测试一样验证证书，信任 CA 后还需要域名匹配
+    #[cfg(feature = "tls-inspect")]
+    {
+        use crate::internal::network::http::Protocol;
+        use crate::internal::network::tls::{self, TlsOptions};
+        let timeout = Duration::from_secs(5);
+        let mut options = TlsOptions::default();
+        let inspect =
+            |options: &TlsOptions, sni| tls::inspect(addr, sni, timeout, options, Protocol::Auto);
+        assert!(inspect(&options, "cf.example.com").is_err());
+        options.ca_pem = Some(std::fs::read(&ca_file).unwrap());
+        assert!(inspect(&options, "cf.example.com").is_ok());
+        assert!(inspect(&options, "other.example.com").is_err());
+        options.verify = false;
+        assert!(inspect(&options, "other.example.com").is_ok());
+    }
+
+    // 信任额外的 CA
+    conf.tls.verify = true;
+    conf.tls.ca_file = ca_file.to_str().unwrap().to_string();
+    conf.tls.min_version = "1.2".to_string();
+    let report = runner::run(&conf, &ips);
+    assert_eq!(report.conn.len(), 1);
+    assert_eq!(report.download.len(), 1);
+
+    // 服务器要求客户端证书
+    let (client_cert, client_key) = self_signed("client");
+    let mtls = tls_server(&cert, &key, Some(client_cert.clone()));
+    conf.ports = vec![mtls.port()];
+    let report = runner::run(&conf, &ips);
+    assert!(report.conn.is_empty());
+
+    let cert_file = dir.join("client.pem");
+    let key_file = dir.join("client.key");
+    std::fs::write(&cert_file, client_cert.to_pem().unwrap()).unwrap();
+    std::fs::write(&key_file, client_key.private_key_to_pem_pkcs8().unwrap()).unwrap();
+    conf.tls.client_cert = cert_file.to_str().unwrap().to_string();
+    let errors = conf.validate();
+    assert!(errors.iter().any(|x| x.starts_with("tls:")));
+    conf.tls.client_key = key_file.to_str().unwrap().to_string();
+    assert!(conf.validate().is_empty());
+    let report = runner::run(&conf, &ips);
+    assert_eq!(report.conn.len(), 1);
+    assert_eq!(report.download.len(), 1);
+    assert_eq!(
+        report.download[0].tls.is_some(),
+        cfg!(feature = "tls-inspect")
+    );
+
+    conf.tls.min_version = "1.3".to_string();
+    assert!(conf.validate().iter().any(|x| x.starts_with("tls:")));
+
+    // 服务器只支持 HTTP/1.1，Host 与 SNI 不同时 http2 是配置错误，auto 使用 HTTP/1.1
+    let addr = tls_server(&cert, &key, None);
+    conf = example_config();
+    conf.url = "https://cf.example.com/download".to_string();
+    conf.ports = vec![addr.port()];
+    conf.history.path = String::new();
+    conf.protocol = "http2".to_string();
+    conf.tls.verify = false;
+    let report = runner::run(&conf, &ips);
+    assert!(report.conn.is_empty());
+    conf.request.host = "origin.example.com".to_string();
+    assert!(conf
+        .validate()
+        .iter()
+        .any(|x| x.starts_with("request.host:")));
+    conf.request.sni = "origin.example.com".to_string();
+    assert!(conf.validate().is_empty());
+    conf.request.sni = String::new();
+    conf.protocol = "auto".to_string();
+    assert!(conf.validate().is_empty());
+    let report = runner::run(&conf, &ips);
+    assert_eq!(report.download.len(), 1);
+    assert_eq!(report.download[0].protocol.as_deref(), Some("HTTP/1.1"));
+
+    std::fs::remove_dir_all(&dir).unwrap();
 }